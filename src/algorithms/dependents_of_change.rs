@@ -0,0 +1,134 @@
+use super::super::bazel::BazelDependencyGraph;
+use super::super::error::{DepsawError, Result};
+use super::super::git::GitRepo;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CommitTriggers {
+    pub commit: String,
+    /// the number of files touched by the commit.
+    pub files: usize,
+    /// the number of targets rebuilt because of the commit, including the
+    /// targets owning the touched files.
+    pub triggered_targets_count: usize,
+    /// the rebuilt targets, when requested.
+    pub triggered_targets: Option<Vec<String>>,
+}
+
+/// Finds every target transitively triggered by each of the given commits.
+/// Commits may be abbreviated, as long as they are unambiguous.
+pub fn dependents_of_change(
+    commits: &[String],
+    repo: &GitRepo,
+    deps_graph: &BazelDependencyGraph,
+    list_targets: bool,
+) -> Result<Vec<CommitTriggers>> {
+    let files_by_commit = repo.files_by_commit();
+    let targets_by_path = deps_graph.targets_by_source_path();
+
+    let mut results = Vec::new();
+    for commit in commits {
+        let matches: Vec<_> = files_by_commit
+            .keys()
+            .filter(|c| c.starts_with(commit.as_str()))
+            .collect();
+        let full_commit = match matches.as_slice() {
+            [full_commit] => *full_commit,
+            [] => {
+                return Err(DepsawError::InvalidInput(format!(
+                    "commit {} not found in the git analysis, check that it is within the analyzed window (--since)",
+                    commit
+                )))
+            }
+            _ => {
+                return Err(DepsawError::InvalidInput(format!(
+                    "commit {} is ambiguous",
+                    commit
+                )))
+            }
+        };
+
+        let files = &files_by_commit[full_commit];
+        let owners: Vec<String> = files
+            .iter()
+            .filter_map(|f| targets_by_path.get(f))
+            .flatten()
+            .cloned()
+            .collect();
+        let mut triggered: Vec<String> = deps_graph.transitive_rdeps(&owners).into_iter().collect();
+        triggered.sort();
+
+        results.push(CommitTriggers {
+            commit: full_commit.clone(),
+            files: files.len(),
+            triggered_targets_count: triggered.len(),
+            triggered_targets: list_targets.then_some(triggered),
+        });
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{generated, graph, repo, rule, source};
+
+    fn fixture() -> (GitRepo, BazelDependencyGraph) {
+        let deps_graph = graph(&[
+            rule("//app:bin", "java_binary", &["//lib:lib"]),
+            rule(
+                "//lib:lib",
+                "java_library",
+                &["//lib:Lib.java", "//proto:A.java"],
+            ),
+            rule("//proto:a_gen", "genrule", &["//proto:a_proto"]),
+            generated("//proto:A.java", "//proto:a_gen"),
+            rule("//proto:a_proto", "proto_library", &["//proto:a.proto"]),
+            rule("//other:lib", "java_library", &["//other:Other.java"]),
+            source("//lib:Lib.java"),
+            source("//proto:a.proto"),
+            source("//other:Other.java"),
+        ]);
+        let git_repo = repo(&[
+            ("proto/a.proto", &["abc123"]),
+            ("lib/Lib.java", &["abd456"]),
+            ("other/Other.java", &["abd456"]),
+        ]);
+        (git_repo, deps_graph)
+    }
+
+    #[test]
+    fn changes_trigger_the_owners_and_their_transitive_rdeps() {
+        let (git_repo, deps_graph) = fixture();
+        let commits = vec!["abc".to_string(), "abd456".to_string()];
+        let results = dependents_of_change(&commits, &git_repo, &deps_graph, true).unwrap();
+        let summary: Vec<_> = results
+            .iter()
+            .map(|r| (r.commit.as_str(), r.files, r.triggered_targets_count))
+            .collect();
+        assert_eq!(summary, vec![("abc123", 1, 4), ("abd456", 2, 3)]);
+        // the proto change reaches //lib:lib through the generated file.
+        assert_eq!(
+            results[0].triggered_targets.as_deref().unwrap(),
+            ["//app:bin", "//lib:lib", "//proto:a_gen", "//proto:a_proto"]
+        );
+        assert_eq!(
+            results[1].triggered_targets.as_deref().unwrap(),
+            ["//app:bin", "//lib:lib", "//other:lib"]
+        );
+
+        let results = dependents_of_change(&commits, &git_repo, &deps_graph, false).unwrap();
+        assert!(results.iter().all(|r| r.triggered_targets.is_none()));
+    }
+
+    #[test]
+    fn unknown_and_ambiguous_commits_are_rejected() {
+        let (git_repo, deps_graph) = fixture();
+        for (commit, error) in [("ab", "is ambiguous"), ("fff", "not found")] {
+            let err = dependents_of_change(&[commit.to_string()], &git_repo, &deps_graph, false)
+                .unwrap_err();
+            assert!(matches!(err, DepsawError::InvalidInput(_)));
+            assert!(err.to_string().contains(error), "{}", err);
+        }
+    }
+}
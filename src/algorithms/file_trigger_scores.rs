@@ -0,0 +1,132 @@
+use super::super::bazel::{self, BazelDependencyGraph};
+use super::super::error::Result;
+use super::super::git::GitRepo;
+use super::trigger_scores::calculate_trigger_scores;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileTriggerScore {
+    /// the path of the source file, relative to the workspace root.
+    pub path: String,
+    /// the target listing the file as a source.
+    pub target: String,
+    /// number of commits touching the file.
+    pub commits: usize,
+    /// number of targets that depend on the owning target, and so rebuild
+    /// whenever the file changes.
+    pub dependents: usize,
+    /// commits * (dependents + 1), counting the owning target as rebuilt
+    /// too, the same way as a target's trigger score.
+    pub score: usize,
+}
+
+/// Attributes trigger scores to the individual source files in the
+/// transitive closure of the given targets, sorted by score. A file listed
+/// as a source of several targets gets a row for each of them.
+pub fn file_trigger_scores(
+    targets: &[String],
+    repo: &GitRepo,
+    deps_graph: &BazelDependencyGraph,
+) -> Result<Vec<FileTriggerScore>> {
+    let scores_by_target = calculate_trigger_scores(targets, repo, deps_graph)?;
+    let mut results = Vec::new();
+    for (name, target) in scores_by_target.iter() {
+        let rule = deps_graph.get_rule(name)?;
+        for source_file in rule.source_files.iter() {
+            // we don't care about remote dependencies
+            let Some(path) = bazel::label_to_path(source_file) else {
+                continue;
+            };
+            let commits = repo
+                .files
+                .get(&path)
+                .map_or(0, |file| file.commit_history.len());
+            results.push(FileTriggerScore {
+                path,
+                target: name.clone(),
+                commits,
+                dependents: target.total_dependents,
+                score: commits * (target.total_dependents + 1),
+            });
+        }
+    }
+    results.sort_by(|a, b| {
+        b.score
+            .cmp(&a.score)
+            .then_with(|| a.path.cmp(&b.path))
+            .then_with(|| a.target.cmp(&b.target))
+    });
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{graph, repo, rule, source};
+
+    #[test]
+    fn files_are_scored_by_their_commits_and_owners_dependents() {
+        let deps_graph = graph(&[
+            rule(
+                "//app:bin",
+                "java_binary",
+                &["//app:Main.java", "//lib:lib"],
+            ),
+            rule(
+                "//lib:lib",
+                "java_library",
+                &["//lib:Lib.java", "//core:Shared.java", "//core:lib"],
+            ),
+            rule(
+                "//core:lib",
+                "java_library",
+                &[
+                    "//core:Core.java",
+                    "//core:Shared.java",
+                    "//core:Untouched.java",
+                    "//core:Util.java",
+                ],
+            ),
+            source("//app:Main.java"),
+            source("//lib:Lib.java"),
+            source("//core:Core.java"),
+            source("//core:Shared.java"),
+            source("//core:Untouched.java"),
+            source("//core:Util.java"),
+        ]);
+        let git_repo = repo(&[
+            ("app/Main.java", &["c1", "c2", "c3", "c4", "c5"]),
+            ("lib/Lib.java", &["c6", "c7"]),
+            ("core/Core.java", &["c8", "c9", "c10"]),
+            ("core/Shared.java", &["c11", "c12"]),
+            ("core/Util.java", &["c13"]),
+        ]);
+        let results =
+            file_trigger_scores(&["//app:bin".to_string()], &git_repo, &deps_graph).unwrap();
+        let rows: Vec<_> = results
+            .iter()
+            .map(|r| {
+                (
+                    r.path.as_str(),
+                    r.target.as_str(),
+                    r.commits,
+                    r.dependents,
+                    r.score,
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("core/Core.java", "//core:lib", 3, 2, 9),
+                ("core/Shared.java", "//core:lib", 2, 2, 6),
+                ("app/Main.java", "//app:bin", 5, 0, 5),
+                // a file shared between targets gets a row for each.
+                ("core/Shared.java", "//lib:lib", 2, 1, 4),
+                ("lib/Lib.java", "//lib:lib", 2, 1, 4),
+                ("core/Util.java", "//core:lib", 1, 2, 3),
+                ("core/Untouched.java", "//core:lib", 0, 2, 0),
+            ]
+        );
+    }
+}
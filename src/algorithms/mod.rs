@@ -1,5 +1,13 @@
+pub mod dependents_of_change;
+pub mod file_trigger_scores;
 pub mod most_unique_triggers;
+pub mod removal_suggestions;
+pub mod score_diff;
 pub mod trigger_scores;
 
+pub use self::dependents_of_change::dependents_of_change;
+pub use self::file_trigger_scores::file_trigger_scores;
 pub use self::most_unique_triggers::most_unique_triggers;
+pub use self::removal_suggestions::{removal_suggestions, Suggestions};
+pub use self::score_diff::score_diff;
 pub use self::trigger_scores::calculate_trigger_scores;
@@ -1,7 +1,7 @@
 use super::super::bazel::BazelDependencyGraph;
+use super::super::error::Result;
 use super::super::git::GitRepo;
 use super::calculate_trigger_scores;
-use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 
@@ -9,42 +9,42 @@ use std::collections::HashSet;
 pub struct Dependency {
     pub name: String,
     pub score: usize,
+    /// the path from the analyzed target to the dependency. The edge to cut
+    /// is between the last two targets.
+    pub path: Vec<String>,
 }
 
-/// Calculates which dependencies would save the most triggers if removed.
-/// Returns a map of dependency name to potential trigger reduction score.
+/// Calculates which dependencies would save the most triggers if removed,
+/// considering dependencies up to `depth` edges away from the target.
+///
+/// Dependencies pulled in through multiple paths, including through a cycle,
+/// can't be removed by cutting a single edge and are skipped. Each other
+/// dependency is reached through a single edge, the last of the reported
+/// path, and its score counts the commits of the dependencies only pulled in
+/// through it, each commit once.
 pub fn most_unique_triggers(
     target: &str,
     repo: &GitRepo,
     deps_graph: &BazelDependencyGraph,
+    depth: usize,
 ) -> Result<Vec<Dependency>> {
     // Get all scores for the dependency graph
-    let scores_by_target = calculate_trigger_scores(target, repo, deps_graph)?;
-
-    // Get the immediate dependencies of our target
-    let target_rule = deps_graph
-        .rules_by_label
-        .get(target)
-        .ok_or(anyhow!("Target {} not found in dependency graph", target))?;
+    let scores_by_target = calculate_trigger_scores(&[target.to_string()], repo, deps_graph)?;
 
     // Find duplicate dependencies (deps that are pulled in through multiple paths)
-    let duplicate_deps = find_duplicate_deps(target, deps_graph)?;
+    let duplicate_deps = find_duplicate_deps(target, deps_graph);
 
-    // Calculate potential savings for each immediate dependency
     let mut deps = Vec::new();
-
-    for dep in &target_rule.dep_targets {
+    for path in shortest_paths(target, deps_graph, depth) {
+        let dep = &path[path.len() - 1];
         // Skip if this is a duplicate dependency
         if duplicate_deps.contains(dep) {
             continue;
         }
 
-        // Get all unique dependencies that would be removed if we removed this dep
-        let unique_deps = get_unique_deps(dep, deps_graph, &duplicate_deps)?;
-
         // Calculate total score from all unique deps
         let mut commits = HashSet::new();
-        for unique_dep in unique_deps {
+        for unique_dep in get_unique_deps(target, dep, deps_graph, &duplicate_deps) {
             if let Some(dep_score) = scores_by_target.get(&unique_dep) {
                 commits.extend(dep_score.commits.iter().cloned());
             }
@@ -53,76 +53,324 @@ pub fn most_unique_triggers(
         deps.push(Dependency {
             name: dep.clone(),
             score: commits.len(),
+            path,
         });
     }
 
-    // sort by score
-    deps.sort_by(|a, b| b.score.cmp(&a.score));
+    // sort by score, breaking ties by name so the order is stable
+    deps.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.name.cmp(&b.name)));
 
     Ok(deps)
 }
 
-/// Finds dependencies that are pulled in through multiple paths in the graph
-fn find_duplicate_deps(target: &str, deps_graph: &BazelDependencyGraph) -> Result<HashSet<String>> {
+/// the shortest path from the target to each of its dependencies at most
+/// `depth` edges away, in breadth first order. Dependencies missing from the
+/// graph have no deps of their own to follow.
+fn shortest_paths(
+    target: &str,
+    deps_graph: &BazelDependencyGraph,
+    depth: usize,
+) -> Vec<Vec<String>> {
+    let mut visited = HashSet::from([target.to_string()]);
+    let mut frontier = vec![vec![target.to_string()]];
+    let mut paths = Vec::new();
+    for _ in 0..depth {
+        let mut next = Vec::new();
+        for path in frontier {
+            let Some(rule) = deps_graph.rules_by_label.get(&path[path.len() - 1]) else {
+                continue;
+            };
+            for dep in rule.dep_targets.iter() {
+                if visited.insert(dep.clone()) {
+                    let mut dep_path = path.clone();
+                    dep_path.push(dep.clone());
+                    next.push(dep_path);
+                }
+            }
+        }
+        paths.extend(next.iter().cloned());
+        frontier = next;
+    }
+    paths
+}
+
+/// Finds dependencies that are pulled in through multiple paths in the graph.
+/// When the target is part of a cycle, its immediate deps are reached both
+/// directly and through the cycle, so they are all duplicates.
+fn find_duplicate_deps(target: &str, deps_graph: &BazelDependencyGraph) -> HashSet<String> {
     let mut seen = HashSet::new();
     let mut duplicates = HashSet::new();
-
-    fn visit_deps(
-        current: &str,
-        deps_graph: &BazelDependencyGraph,
-        seen: &mut HashSet<String>,
-        duplicates: &mut HashSet<String>,
-    ) -> Result<()> {
-        let rule = deps_graph
-            .rules_by_label
-            .get(current)
-            .ok_or(anyhow!("Target {} not found in dependency graph", current))?;
-
-        for dep in &rule.dep_targets {
-            if !seen.insert(dep.clone()) {
-                duplicates.insert(dep.clone());
+    let mut stack = vec![target.to_string()];
+    while let Some(current) = stack.pop() {
+        let Some(rule) = deps_graph.rules_by_label.get(&current) else {
+            continue;
+        };
+        for dep in rule.dep_targets.iter() {
+            if seen.insert(dep.clone()) {
+                stack.push(dep.clone());
             } else {
-                visit_deps(dep, deps_graph, seen, duplicates)?;
+                duplicates.insert(dep.clone());
             }
         }
-        Ok(())
     }
-
-    visit_deps(target, deps_graph, &mut seen, &mut duplicates)?;
-    Ok(duplicates)
+    duplicates
 }
 
-/// Gets all unique dependencies that would be removed if we removed the given dep
+/// Gets all unique dependencies that would be removed if we removed the given
+/// dep: the dep, and the deps only pulled in through it. The target itself is
+/// never removed.
 fn get_unique_deps(
+    target: &str,
     dep: &str,
     deps_graph: &BazelDependencyGraph,
     duplicate_deps: &HashSet<String>,
-) -> Result<HashSet<String>> {
+) -> HashSet<String> {
     let mut unique_deps = HashSet::new();
+    let mut stack = vec![dep.to_string()];
+    while let Some(current) = stack.pop() {
+        if current == target
+            || duplicate_deps.contains(&current)
+            || !unique_deps.insert(current.clone())
+        {
+            continue;
+        }
+        if let Some(rule) = deps_graph.rules_by_label.get(&current) {
+            stack.extend(rule.dep_targets.iter().cloned());
+        }
+    }
+    unique_deps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{graph, repo, rule, source};
+
+    #[test]
+    fn deps_free_nothing_without_commits() {
+        let deps_graph = graph(&[
+            rule("//app:bin", "java_binary", &["//a:lib"]),
+            rule("//a:lib", "java_library", &["//a:A.java"]),
+            source("//a:A.java"),
+        ]);
+        let deps = most_unique_triggers("//app:bin", &repo(&[]), &deps_graph, 2).unwrap();
+        let scores: Vec<_> = deps.iter().map(|d| (d.name.as_str(), d.score)).collect();
+        assert_eq!(scores, vec![("//a:lib", 0)]);
+    }
+
+    fn scores(deps: &[Dependency]) -> Vec<(&str, usize)> {
+        deps.iter().map(|d| (d.name.as_str(), d.score)).collect()
+    }
+
+    #[test]
+    fn heavy_subtree_behind_a_wrapper_is_found_at_depth_2() {
+        let deps_graph = graph(&[
+            rule(
+                "//app:bin",
+                "java_binary",
+                &["//app:wrapper", "//lib/util:lib"],
+            ),
+            rule(
+                "//app:wrapper",
+                "java_library",
+                &["//app:Wrapper.java", "//lib/a:lib", "//lib/util:lib"],
+            ),
+            rule(
+                "//lib/a:lib",
+                "java_library",
+                &["//lib/a:A.java", "//lib/heavy:x", "//lib/heavy:y"],
+            ),
+            rule("//lib/heavy:x", "java_library", &["//lib/heavy:X.java"]),
+            rule("//lib/heavy:y", "java_library", &["//lib/heavy:Y.java"]),
+            rule("//lib/util:lib", "java_library", &["//lib/util:Util.java"]),
+            source("//app:Wrapper.java"),
+            source("//lib/a:A.java"),
+            source("//lib/heavy:X.java"),
+            source("//lib/heavy:Y.java"),
+            source("//lib/util:Util.java"),
+        ]);
+        let git_repo = repo(&[
+            ("app/Wrapper.java", &["c1"]),
+            ("lib/a/A.java", &["c2"]),
+            ("lib/heavy/X.java", &["c3", "c4"]),
+            // a commit touching both heavy deps is counted once.
+            ("lib/heavy/Y.java", &["c4", "c5"]),
+            ("lib/util/Util.java", &["c6"]),
+        ]);
+
+        let deps = most_unique_triggers("//app:bin", &git_repo, &deps_graph, 1).unwrap();
+        // util is also a direct dep, so removing it from the wrapper frees
+        // nothing.
+        assert_eq!(scores(&deps), vec![("//app:wrapper", 5)]);
 
-    fn visit_deps(
-        current: &str,
-        deps_graph: &BazelDependencyGraph,
-        duplicate_deps: &HashSet<String>,
-        unique_deps: &mut HashSet<String>,
-    ) -> Result<()> {
-        if duplicate_deps.contains(current) {
-            return Ok(());
+        let deps = most_unique_triggers("//app:bin", &git_repo, &deps_graph, 2).unwrap();
+        assert_eq!(
+            scores(&deps),
+            vec![("//app:wrapper", 5), ("//lib/a:lib", 4)]
+        );
+        assert_eq!(
+            deps[1].path,
+            vec!["//app:bin", "//app:wrapper", "//lib/a:lib"]
+        );
+
+        let deps = most_unique_triggers("//app:bin", &git_repo, &deps_graph, 3).unwrap();
+        assert_eq!(
+            scores(&deps),
+            vec![
+                ("//app:wrapper", 5),
+                ("//lib/a:lib", 4),
+                ("//lib/heavy:x", 2),
+                ("//lib/heavy:y", 2),
+            ]
+        );
+        assert_eq!(
+            deps[2].path,
+            vec!["//app:bin", "//app:wrapper", "//lib/a:lib", "//lib/heavy:x"]
+        );
+    }
+
+    #[test]
+    fn shared_subtree_of_a_diamond_is_not_freed() {
+        let deps_graph = graph(&[
+            rule("//app:bin", "java_binary", &["//a:lib", "//b:lib"]),
+            rule("//a:lib", "java_library", &["//a:A.java", "//shared:lib"]),
+            rule("//b:lib", "java_library", &["//shared:lib"]),
+            rule(
+                "//shared:lib",
+                "java_library",
+                &["//shared:S.java", "//deep:lib"],
+            ),
+            rule("//deep:lib", "java_library", &["//deep:D.java"]),
+            source("//a:A.java"),
+            source("//shared:S.java"),
+            source("//deep:D.java"),
+        ]);
+        let git_repo = repo(&[
+            ("a/A.java", &["c1"]),
+            ("shared/S.java", &["c2"]),
+            ("deep/D.java", &["c3"]),
+        ]);
+        let deps = most_unique_triggers("//app:bin", &git_repo, &deps_graph, 3).unwrap();
+        // cutting shared -> deep frees deep, but shared itself is reached
+        // through both a and b.
+        assert_eq!(
+            scores(&deps),
+            vec![("//a:lib", 1), ("//deep:lib", 1), ("//b:lib", 0)]
+        );
+        assert_eq!(
+            deps[1].path,
+            vec!["//app:bin", "//a:lib", "//shared:lib", "//deep:lib"]
+        );
+    }
+
+    #[test]
+    fn cycles_are_not_double_counted() {
+        let deps_graph = graph(&[
+            rule("//app:bin", "java_binary", &["//a:lib"]),
+            rule("//a:lib", "java_library", &["//a:A.java", "//b:lib"]),
+            rule(
+                "//b:lib",
+                "java_library",
+                &["//b:B.java", "//a:lib", "//c:lib"],
+            ),
+            rule("//c:lib", "java_library", &["//c:C.java"]),
+            source("//a:A.java"),
+            source("//b:B.java"),
+            source("//c:C.java"),
+        ]);
+        let git_repo = repo(&[
+            ("a/A.java", &["c1"]),
+            ("b/B.java", &["c2"]),
+            ("c/C.java", &["c3"]),
+        ]);
+        for depth in [1, 2, 5] {
+            let deps = most_unique_triggers("//app:bin", &git_repo, &deps_graph, depth).unwrap();
+            let expected = [("//b:lib", 2), ("//c:lib", 1)];
+            // a is reached both from the target and through b.
+            assert_eq!(scores(&deps), expected[..(depth - 1).min(2)], "{}", depth);
         }
 
-        unique_deps.insert(current.to_string());
+        // the target itself in a cycle is never freed.
+        let deps_graph = graph(&[
+            rule("//a:lib", "java_library", &["//a:A.java", "//b:lib"]),
+            rule("//b:lib", "java_library", &["//b:B.java", "//c:lib"]),
+            rule("//c:lib", "java_library", &["//c:C.java", "//a:lib"]),
+            source("//a:A.java"),
+            source("//b:B.java"),
+            source("//c:C.java"),
+        ]);
+        let deps = most_unique_triggers("//a:lib", &git_repo, &deps_graph, 2).unwrap();
+        assert_eq!(scores(&deps), vec![("//c:lib", 1)]);
+    }
+
+    /// the results of the original, immediate deps only, implementation.
+    #[test]
+    fn depth_1_scores_are_unchanged() {
+        let deps_graph = graph(&[
+            rule(
+                "//app:bin",
+                "java_binary",
+                &["//a:lib", "//b:lib", "//c:lib", "//d:lib"],
+            ),
+            // x and y share z.
+            rule(
+                "//a:lib",
+                "java_library",
+                &["//a:A.java", "//x:lib", "//y:lib"],
+            ),
+            rule("//x:lib", "java_library", &["//x:X.java", "//z:lib"]),
+            rule("//y:lib", "java_library", &["//z:lib"]),
+            rule("//z:lib", "java_library", &["//z:Z.java"]),
+            rule("//b:lib", "java_library", &["//c:lib"]),
+            rule("//c:lib", "java_library", &["//c:C.java"]),
+            rule("//d:lib", "java_library", &[]),
+            source("//a:A.java"),
+            source("//x:X.java"),
+            source("//z:Z.java"),
+            source("//c:C.java"),
+        ]);
+        let git_repo = repo(&[
+            ("a/A.java", &["c1"]),
+            ("x/X.java", &["c2", "c3"]),
+            ("z/Z.java", &["c4"]),
+            ("c/C.java", &["c5"]),
+        ]);
+        let deps = most_unique_triggers("//app:bin", &git_repo, &deps_graph, 1).unwrap();
+        // z is pulled in through both x and y, so it isn't counted for a,
+        // and c is pulled in directly and through b.
+        assert_eq!(
+            scores(&deps),
+            vec![("//a:lib", 3), ("//b:lib", 0), ("//d:lib", 0)]
+        );
+    }
 
-        let rule = deps_graph
+    #[test]
+    fn paths_skip_deps_missing_from_the_graph() {
+        let mut deps_graph = graph(&[
+            rule("//app:bin", "java_binary", &["//a:lib"]),
+            rule("//a:lib", "java_library", &[]),
+        ]);
+        deps_graph
             .rules_by_label
-            .get(current)
-            .ok_or(anyhow!("Target {} not found in dependency graph", current))?;
+            .get_mut("//app:bin")
+            .unwrap()
+            .dep_targets
+            .push("//missing:lib".to_string());
+        assert_eq!(
+            shortest_paths("//app:bin", &deps_graph, 3),
+            vec![
+                vec!["//app:bin".to_string(), "//a:lib".to_string()],
+                vec!["//app:bin".to_string(), "//missing:lib".to_string()],
+            ]
+        );
+    }
 
-        for dep in &rule.dep_targets {
-            visit_deps(dep, deps_graph, duplicate_deps, unique_deps)?;
+    #[test]
+    fn target_without_deps_has_no_results() {
+        let deps_graph = graph(&[rule("//a:lib", "java_library", &[])]);
+        for git_repo in [repo(&[]), repo(&[("a/A.java", &["c1"])])] {
+            let deps = most_unique_triggers("//a:lib", &git_repo, &deps_graph, 2).unwrap();
+            assert!(deps.is_empty());
         }
-        Ok(())
     }
-
-    visit_deps(dep, deps_graph, duplicate_deps, &mut unique_deps)?;
-    Ok(unique_deps)
 }
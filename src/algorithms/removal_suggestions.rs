@@ -0,0 +1,170 @@
+use super::super::analysis_file;
+use super::super::bazel::BazelDependencyGraph;
+use super::super::error::Result;
+use super::most_unique_triggers::Dependency;
+use serde::{Deserialize, Serialize};
+
+/// How risky it is to remove a dependency, computed from cheap signals in the
+/// dependency graph without running any builds.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Confidence {
+    /// nothing in the dep's subtree changed in the analyzed window.
+    LowRisk,
+    MediumRisk,
+    /// the dep re-exports other targets, which the target may be relying on
+    /// transitively.
+    HighRisk,
+    /// the target listing the dep also lists it in its own `exports`, so
+    /// removing it from `deps` would break consumers of that target.
+    DoNotTouch,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Suggestion {
+    pub dep: String,
+    /// the target the dep is removed from. This is the analyzed target
+    /// itself, unless the dep was found further down the graph.
+    pub from_target: String,
+    /// the number of triggers removed from the target if the dep is removed.
+    pub score: usize,
+    pub confidence: Confidence,
+    /// the buildozer invocation that removes the dep from `from_target`.
+    pub buildozer_command: String,
+    /// tests in the dependency graph that depend on `from_target`, and
+    /// therefore verify the removal. A graph of the target's own deps has
+    /// no tests depending on the target itself, so this is often empty, in
+    /// which case `removable-deps` asks bazel for them instead.
+    pub verify_tests: Vec<String>,
+}
+
+/// A document of removal suggestions for a single target, as consumed by
+/// `removable-deps --from-suggestions`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Suggestions {
+    pub target: String,
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl Suggestions {
+    pub fn from_file(path: &str) -> Result<Suggestions> {
+        let content = analysis_file::read(path)?;
+        Ok(serde_yaml::from_slice(&content)?)
+    }
+
+    pub fn to_file(&self, path: &str) -> Result<()> {
+        analysis_file::write(path, serde_yaml::to_string(self)?.as_bytes())?;
+        Ok(())
+    }
+
+    /// the suggestions worth verifying, highest score first.
+    pub fn top(&self, n: usize) -> Vec<&Suggestion> {
+        let mut suggestions: Vec<_> = self
+            .suggestions
+            .iter()
+            .filter(|s| s.confidence != Confidence::DoNotTouch)
+            .collect();
+        suggestions.sort_by_key(|s| std::cmp::Reverse(s.score));
+        suggestions.truncate(n);
+        suggestions
+    }
+}
+
+/// Turn the results of `most_unique_triggers` into actionable suggestions.
+pub fn removal_suggestions(
+    target: &str,
+    deps: &[Dependency],
+    deps_graph: &BazelDependencyGraph,
+) -> Result<Suggestions> {
+    let mut suggestions = Vec::new();
+    for dep in deps {
+        let from_target = &dep.path[dep.path.len() - 2];
+        let from_rule = deps_graph.get_rule(from_target)?;
+        let dep_rule = deps_graph.get_rule(&dep.name)?;
+        let confidence = if from_rule.exports.contains(&dep.name) {
+            Confidence::DoNotTouch
+        } else if !dep_rule.exports.is_empty() {
+            Confidence::HighRisk
+        } else if dep.score == 0 {
+            Confidence::LowRisk
+        } else {
+            Confidence::MediumRisk
+        };
+        suggestions.push(Suggestion {
+            dep: dep.name.clone(),
+            from_target: from_target.clone(),
+            score: dep.score,
+            confidence,
+            buildozer_command: format!("buildozer 'remove deps {}' {}", dep.name, from_target),
+            verify_tests: tests_depending_on(from_target, deps_graph),
+        });
+    }
+
+    Ok(Suggestions {
+        target: target.to_string(),
+        suggestions,
+    })
+}
+
+/// the test rules in the graph that depend on the target, sorted.
+fn tests_depending_on(target: &str, deps_graph: &BazelDependencyGraph) -> Vec<String> {
+    let mut tests: Vec<String> = deps_graph
+        .transitive_rdeps(&[target.to_string()])
+        .into_iter()
+        .filter(|label| {
+            deps_graph
+                .rules_by_label
+                .get(label)
+                .is_some_and(|e| e.rule_class.ends_with("_test"))
+        })
+        .collect();
+    tests.sort();
+    tests
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{graph, rule};
+
+    fn dependency(path: &[&str], score: usize) -> Dependency {
+        Dependency {
+            name: path[path.len() - 1].to_string(),
+            score,
+            path: path.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn verify_tests_depend_on_the_target_the_dep_is_removed_from() {
+        let deps_graph = graph(&[
+            rule("//app:bin", "java_binary", &["//w:wrapper", "//b:lib"]),
+            rule("//w:wrapper", "java_library", &["//a:lib"]),
+            rule("//w:wrapper_test", "java_test", &["//w:wrapper"]),
+            rule("//a:lib", "java_library", &[]),
+            rule("//b:lib", "java_library", &[]),
+        ]);
+        let suggestions = removal_suggestions(
+            "//app:bin",
+            &[
+                dependency(&["//app:bin", "//w:wrapper", "//a:lib"], 3),
+                dependency(&["//app:bin", "//b:lib"], 0),
+            ],
+            &deps_graph,
+        )
+        .unwrap();
+        let a = &suggestions.suggestions[0];
+        assert_eq!(a.from_target, "//w:wrapper");
+        assert_eq!(a.verify_tests, vec!["//w:wrapper_test"]);
+        assert_eq!(
+            a.buildozer_command,
+            "buildozer 'remove deps //a:lib' //w:wrapper"
+        );
+        assert_eq!(a.confidence, Confidence::MediumRisk);
+        // nothing in the graph depends on the analyzed target.
+        let b = &suggestions.suggestions[1];
+        assert_eq!(b.from_target, "//app:bin");
+        assert!(b.verify_tests.is_empty());
+        assert_eq!(b.confidence, Confidence::LowRisk);
+    }
+}
@@ -0,0 +1,293 @@
+//! Comparing the trigger scores of two analyses, such as this week's against
+//! last week's.
+//!
+//! Raw deltas are only comparable when both analyses cover the same length
+//! of history. With window alignment, the windows recorded in each run's
+//! metadata envelope are checked to be the same length, within a tolerance,
+//! and the deltas are also reported per day of history, so that comparing a
+//! 6 day window against a 7 day one doesn't show phantom regressions.
+use super::super::analysis_file;
+use super::super::error::{DepsawError, Result};
+use super::super::git::Window;
+use super::trigger_scores::ResolvedTarget;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use tracing::warn;
+
+const WEEKDAYS: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ScoreDelta {
+    pub name: String,
+    /// the score in the baseline, 0 if the target wasn't in it.
+    pub baseline_score: usize,
+    /// the score in the current analysis, 0 if the target isn't in it.
+    pub current_score: usize,
+    pub score_delta: i64,
+    pub rebuilds_delta: i64,
+    /// the change in score per day of history, when the windows were
+    /// aligned.
+    pub score_per_day_delta: Option<f64>,
+    /// the change in rebuilds per day of history, when the windows were
+    /// aligned.
+    pub rebuilds_per_day_delta: Option<f64>,
+}
+
+/// read trigger scores written by `trigger-scores-map` as JSON or YAML.
+pub fn read_scores(path: &str) -> Result<Vec<(String, ResolvedTarget)>> {
+    let content = analysis_file::read(path)?;
+    // JSON is a subset of YAML, so a single parser reads both.
+    serde_yaml::from_slice(&content).map_err(|e| {
+        DepsawError::ParseError(format!(
+            "{} is not trigger-scores-map output in JSON or YAML: {}",
+            path, e
+        ))
+    })
+}
+
+/// check that the windows of the two analyses can be compared, returning
+/// their lengths in days to normalize the deltas by. Windows that can't be
+/// aligned are warned about and left unnormalized, or are an error when
+/// `strict`.
+pub fn align_windows(
+    baseline: Option<Window>,
+    current: Option<Window>,
+    tolerance_days: f64,
+    strict: bool,
+) -> Result<Option<(f64, f64)>> {
+    let unaligned = |reason: String| {
+        if strict {
+            return Err(DepsawError::InvalidInput(format!(
+                "unable to align the analysis windows: {}",
+                reason
+            )));
+        }
+        warn!("{}, reporting raw deltas only", reason);
+        Ok(None)
+    };
+    let (Some(baseline), Some(current)) = (baseline, current) else {
+        return unaligned(
+            "both analyses need window metadata, pass their --metadata-output files".to_string(),
+        );
+    };
+    let (baseline_days, current_days) = (baseline.days(), current.days());
+    if baseline_days <= 0.0 || current_days <= 0.0 {
+        return unaligned("an analysis window is empty".to_string());
+    }
+    if (baseline_days - current_days).abs() > tolerance_days {
+        return unaligned(format!(
+            "the baseline covers {:.2} days of history and the current analysis {:.2}, more than {} apart",
+            baseline_days, current_days, tolerance_days
+        ));
+    }
+    if baseline.end_weekday() != current.end_weekday() {
+        warn!(
+            "the baseline window ends on a {} and the current one on a {}, so weekly commit patterns may differ",
+            WEEKDAYS[baseline.end_weekday() as usize],
+            WEEKDAYS[current.end_weekday() as usize]
+        );
+    }
+    Ok(Some((baseline_days, current_days)))
+}
+
+/// the change in each target's score, largest change first. Deltas are
+/// normalized per day of history when the window lengths are given, and
+/// sorted by the normalized delta then.
+pub fn score_diff(
+    baseline: &[(String, ResolvedTarget)],
+    current: &[(String, ResolvedTarget)],
+    window_days: Option<(f64, f64)>,
+) -> Vec<ScoreDelta> {
+    let baseline: HashMap<&str, &ResolvedTarget> = baseline
+        .iter()
+        .map(|(name, t)| (name.as_str(), t))
+        .collect();
+    let current: HashMap<&str, &ResolvedTarget> =
+        current.iter().map(|(name, t)| (name.as_str(), t)).collect();
+    let names: BTreeSet<&str> = baseline.keys().chain(current.keys()).copied().collect();
+    let per_day = |baseline: usize, current: usize| {
+        window_days.map(|(baseline_days, current_days)| {
+            current as f64 / current_days - baseline as f64 / baseline_days
+        })
+    };
+    let mut deltas: Vec<ScoreDelta> = names
+        .into_iter()
+        .map(|name| {
+            let score = |t: Option<&&ResolvedTarget>| t.map_or(0, |t| t.score);
+            let rebuilds = |t: Option<&&ResolvedTarget>| t.map_or(0, |t| t.rebuilds);
+            let (before, after) = (baseline.get(name), current.get(name));
+            ScoreDelta {
+                name: name.to_string(),
+                baseline_score: score(before),
+                current_score: score(after),
+                score_delta: score(after) as i64 - score(before) as i64,
+                rebuilds_delta: rebuilds(after) as i64 - rebuilds(before) as i64,
+                score_per_day_delta: per_day(score(before), score(after)),
+                rebuilds_per_day_delta: per_day(rebuilds(before), rebuilds(after)),
+            }
+        })
+        .collect();
+    // names are unique, so ties are broken by name.
+    deltas.sort_by(|a, b| {
+        let magnitude =
+            |d: &ScoreDelta| d.score_per_day_delta.unwrap_or(d.score_delta as f64).abs();
+        magnitude(b)
+            .total_cmp(&magnitude(a))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    deltas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    const DAY: i64 = 24 * 60 * 60;
+    /// a Monday.
+    const MONDAY: i64 = 1_700_438_400;
+
+    fn window(days: f64, end: i64) -> Window {
+        Window {
+            start: end - (days * DAY as f64) as i64,
+            end,
+        }
+    }
+
+    fn target(name: &str, rebuilds: usize, total_dependents: usize) -> (String, ResolvedTarget) {
+        (
+            name.to_string(),
+            ResolvedTarget {
+                name: name.to_string(),
+                rebuilds,
+                immediate_dependents: total_dependents,
+                total_dependents,
+                score: rebuilds * (total_dependents + 1),
+                total_lines: None,
+                rebuilds_per_kloc: None,
+                external_dep_count: 0,
+                cost: None,
+                weighted_score: None,
+                triggering_roots: vec![],
+                commits: Default::default(),
+            },
+        )
+    }
+
+    #[test]
+    fn windows_of_the_same_length_are_aligned() {
+        let days = align_windows(
+            Some(window(7.0, MONDAY - 7 * DAY)),
+            Some(window(7.2, MONDAY)),
+            0.5,
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            days.map(|(b, c)| (b, (c * 10.0).round())),
+            Some((7.0, 72.0))
+        );
+    }
+
+    #[test]
+    fn mismatched_windows_are_detected() {
+        let baseline = Some(window(7.0, MONDAY - 7 * DAY));
+        let current = Some(window(6.0, MONDAY));
+        assert_eq!(align_windows(baseline, current, 0.5, false).unwrap(), None);
+        let err = align_windows(baseline, current, 0.5, true).unwrap_err();
+        assert!(matches!(err, DepsawError::InvalidInput(_)));
+        assert!(err.to_string().contains("7.00 days"), "{}", err);
+        assert!(err.to_string().contains("6.00"), "{}", err);
+        // within the tolerance.
+        assert!(align_windows(baseline, current, 1.0, true)
+            .unwrap()
+            .is_some());
+
+        for (baseline, current) in [(None, current), (baseline, None)] {
+            assert_eq!(align_windows(baseline, current, 0.5, false).unwrap(), None);
+            assert!(align_windows(baseline, current, 0.5, true).is_err());
+        }
+        let empty = Some(window(0.0, MONDAY));
+        assert!(align_windows(empty, empty, 0.5, true).is_err());
+    }
+
+    #[test]
+    fn weekdays_are_counted_from_monday() {
+        assert_eq!(window(7.0, MONDAY).end_weekday(), 0);
+        assert_eq!(window(7.0, MONDAY + 6 * DAY + 1).end_weekday(), 6);
+        assert_eq!(window(7.0, 0).end_weekday(), 3);
+    }
+
+    #[test]
+    fn raw_deltas_cover_targets_on_either_side() {
+        let baseline = [target("//a:lib", 2, 1), target("//gone:lib", 1, 0)];
+        let current = [target("//a:lib", 5, 1), target("//new:lib", 3, 0)];
+        let deltas = score_diff(&baseline, &current, None);
+        let summary: Vec<_> = deltas
+            .iter()
+            .map(|d| {
+                (
+                    d.name.as_str(),
+                    d.baseline_score,
+                    d.current_score,
+                    d.score_delta,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("//a:lib", 4, 10, 6),
+                ("//new:lib", 0, 3, 3),
+                ("//gone:lib", 1, 0, -1),
+            ]
+        );
+        assert_eq!(deltas[0].rebuilds_delta, 3);
+        assert!(deltas.iter().all(|d| d.score_per_day_delta.is_none()));
+    }
+
+    #[test]
+    fn deltas_are_normalized_per_day() {
+        // the same rate of 2 rebuilds a day over 6 and 7 days.
+        let baseline = [target("//a:lib", 14, 1), target("//b:lib", 7, 0)];
+        let current = [target("//a:lib", 12, 1), target("//b:lib", 12, 0)];
+        let deltas = score_diff(&baseline, &current, Some((7.0, 6.0)));
+        assert_eq!(deltas[0].name, "//b:lib");
+        assert_eq!(deltas[0].score_delta, 5);
+        assert_eq!(deltas[0].score_per_day_delta, Some(1.0));
+        assert_eq!(deltas[0].rebuilds_per_day_delta, Some(1.0));
+        // a raw regression that is no change in rate.
+        assert_eq!(deltas[1].name, "//a:lib");
+        assert_eq!(deltas[1].score_delta, -4);
+        assert_eq!(deltas[1].score_per_day_delta, Some(0.0));
+        assert_eq!(deltas[1].rebuilds_per_day_delta, Some(0.0));
+    }
+
+    #[test]
+    fn scores_are_read_from_json_and_yaml() {
+        let dir = TempDir::new("score-diff");
+        let scores = vec![target("//a:lib", 2, 1)];
+        let json = dir.join("scores.json");
+        std::fs::write(&json, serde_json::to_string_pretty(&scores).unwrap()).unwrap();
+        let yaml = dir.join("scores.yaml");
+        std::fs::write(&yaml, serde_yaml::to_string(&scores).unwrap()).unwrap();
+        for path in [json, yaml] {
+            let read = read_scores(&path).unwrap();
+            assert_eq!(read, scores, "{}", path);
+        }
+        let csv = dir.join("scores.csv");
+        std::fs::write(&csv, "//a:lib,2,1,1,4,0,,\n").unwrap();
+        assert!(matches!(
+            read_scores(&csv).unwrap_err(),
+            DepsawError::ParseError(_)
+        ));
+    }
+}
@@ -1,17 +1,15 @@
 use super::super::bazel;
+use super::super::error::Result;
 use super::super::git;
-use anyhow::anyhow;
+use super::super::loc::{self, LocStats};
+use super::super::workspace::Workspace;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::rc::Rc;
 use std::sync::RwLock;
-#[derive(Debug, Serialize, Deserialize)]
-pub struct TriggerScores {
-    pub targets: Vec<ResolvedTarget>,
-}
-
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+use tracing::warn;
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub struct ResolvedTarget {
     pub name: String,
     /// number of times the target is rebuilt
@@ -22,6 +20,24 @@ pub struct ResolvedTarget {
     pub total_dependents: usize,
     /// builds. it is currently rebuilds + dependents.
     pub score: usize,
+    /// lines in the source files owned by the target, when requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_lines: Option<usize>,
+    /// rebuilds per thousand lines owned by the target, when requested and
+    /// the target owns any lines.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rebuilds_per_kloc: Option<f64>,
+    /// number of external inputs, e.g. `@maven//:guava`, the target
+    /// depends on directly. These aren't scored, since they have no history.
+    pub external_dep_count: usize,
+    /// the cost of building the target, when a cost file was given.
+    pub cost: Option<f64>,
+    /// score weighted by cost, when a cost file was given.
+    pub weighted_score: Option<f64>,
+    /// the requested targets that depend on this target, when more than one
+    /// target was analyzed. Last, as CSV rows list them as separate columns.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub triggering_roots: Vec<String>,
     /// The commits that trigger this target specifically. Does not include commits
     /// that triggered dependencies.
     #[serde(skip_serializing, skip_deserializing)]
@@ -37,56 +53,46 @@ pub struct Target {
     pub immediate_dependents: Vec<Rc<RwLock<Target>>>,
 }
 
-impl Ord for ResolvedTarget {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.score.cmp(&other.score)
-    }
-}
-
-impl PartialOrd for ResolvedTarget {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.score.cmp(&other.score))
-    }
-}
-
+/// calculate the trigger scores of every target the given targets depend on.
+/// When several targets are given, the results are unioned: a dependency
+/// shared between them appears once, with its commits counted once.
 pub fn calculate_trigger_scores(
-    target: &str,
+    targets: &[String],
     repo: &git::GitRepo,
     deps_graph: &bazel::BazelDependencyGraph,
-) -> anyhow::Result<HashMap<String, ResolvedTarget>> {
+) -> Result<HashMap<String, ResolvedTarget>> {
     let mut commits_by_target = HashMap::new();
     let mut commits_specific_to_target = HashMap::new();
     let mut score_by_target = HashMap::new();
-    if target.ends_with("...") {
-        let prefix = target[..target.len() - 4].to_string();
-        // we grab all targets from the map, in this case.
-        for (t, _) in deps_graph.rules_by_label.iter() {
-            if t.starts_with(&prefix) {
-                calculate_trigger_scores_map_inner(
-                    t,
-                    repo,
-                    deps_graph,
-                    &mut commits_by_target,
-                    &mut commits_specific_to_target,
-                    &mut score_by_target,
-                )?;
+    let mut roots_by_target: HashMap<String, Vec<String>> = HashMap::new();
+    for root in targets {
+        let labels = deps_graph.expand_target_pattern(root)?;
+        for label in labels.iter() {
+            calculate_trigger_scores_map_inner(
+                label,
+                repo,
+                deps_graph,
+                &mut commits_by_target,
+                &mut commits_specific_to_target,
+                &mut score_by_target,
+            )?;
+        }
+        if targets.len() > 1 {
+            for label in deps_graph.transitive_deps(&labels) {
+                roots_by_target.entry(label).or_default().push(root.clone());
             }
         }
-    } else {
-        calculate_trigger_scores_map_inner(
-            target,
-            repo,
-            deps_graph,
-            &mut commits_by_target,
-            &mut commits_specific_to_target,
-            &mut score_by_target,
-        )?;
+    }
+    // the roots are listed in the order they were given, which mustn't
+    // change the results.
+    for roots in roots_by_target.values_mut() {
+        roots.sort();
     }
     let mut result = HashMap::new();
     // calculate values that were not calculatable in the first pass
     for (_, target_rw) in score_by_target.iter_mut() {
         let target = target_rw.read().unwrap();
-        let total_dependents = recursively_calculate_total_dependents(&target_rw);
+        let total_dependents = recursively_calculate_total_dependents(target_rw);
         let score = target.rebuilds * (total_dependents + 1);
         result.insert(
             target.name.clone(),
@@ -94,21 +100,87 @@ pub fn calculate_trigger_scores(
                 name: target.name.clone(),
                 rebuilds: target.rebuilds,
                 immediate_dependents: target.immediate_dependents.len(),
-                total_dependents: total_dependents,
+                total_dependents,
                 score,
+                total_lines: None,
+                rebuilds_per_kloc: None,
+                external_dep_count: deps_graph
+                    .rules_by_label
+                    .get(&target.name)
+                    .map_or(0, |entry| entry.external_dep_count),
+                cost: None,
+                weighted_score: None,
+                triggering_roots: roots_by_target.remove(&target.name).unwrap_or_default(),
+                // a target without any source files touched by commits has
+                // no specific commits.
                 commits: commits_specific_to_target
                     .get(&target.name)
-                    .ok_or(anyhow!(
-                        "target {} not found in commits_specific_to_target",
-                        target.name
-                    ))?
-                    .clone(),
+                    .cloned()
+                    .unwrap_or_default(),
             },
         );
     }
     Ok(result)
 }
 
+/// populate the lines of code fields of each target, counting the target's
+/// source files under the workspace root.
+pub fn add_lines_of_code(
+    scores_by_target: &mut HashMap<String, ResolvedTarget>,
+    workspace: &mut Workspace,
+    deps_graph: &bazel::BazelDependencyGraph,
+) -> Result<LocStats> {
+    let (lines_by_target, stats) =
+        loc::lines_by_target(workspace, deps_graph, scores_by_target.keys())?;
+    for (name, target) in scores_by_target.iter_mut() {
+        let lines = lines_by_target.get(name).copied().unwrap_or(0);
+        target.total_lines = Some(lines);
+        target.rebuilds_per_kloc = if lines == 0 {
+            None
+        } else {
+            Some(target.rebuilds as f64 * 1000.0 / lines as f64)
+        };
+    }
+    Ok(stats)
+}
+
+/// populate the cost fields of each target from the cost of each label.
+/// Targets missing from the costs use the default cost, or are left
+/// unweighted without one. Labels that aren't in the dependency graph are
+/// warned about, since they are likely stale or misspelled.
+pub fn add_costs(
+    scores_by_target: &mut HashMap<String, ResolvedTarget>,
+    costs: &HashMap<String, f64>,
+    default_cost: Option<f64>,
+    deps_graph: &bazel::BazelDependencyGraph,
+) {
+    let unknown = unknown_labels(costs, deps_graph);
+    if !unknown.is_empty() {
+        warn!(
+            "{} labels in the cost file are not in the dependency graph, e.g. {}",
+            unknown.len(),
+            unknown[0]
+        );
+    }
+    for (name, target) in scores_by_target.iter_mut() {
+        target.cost = costs.get(name).copied().or(default_cost);
+        target.weighted_score = target.cost.map(|cost| target.score as f64 * cost);
+    }
+}
+
+/// the labels of the cost file missing from the dependency graph, sorted.
+fn unknown_labels<'a>(
+    costs: &'a HashMap<String, f64>,
+    deps_graph: &bazel::BazelDependencyGraph,
+) -> Vec<&'a String> {
+    let mut unknown: Vec<&String> = costs
+        .keys()
+        .filter(|label| !deps_graph.rules_by_label.contains_key(*label))
+        .collect();
+    unknown.sort();
+    unknown
+}
+
 fn calculate_trigger_scores_map_inner(
     target_name: &str,
     repo: &git::GitRepo,
@@ -116,21 +188,28 @@ fn calculate_trigger_scores_map_inner(
     commits_by_target: &mut HashMap<String, std::collections::HashSet<String>>,
     commits_specific_to_target: &mut HashMap<String, std::collections::HashSet<String>>,
     score_by_target: &mut HashMap<String, Rc<RwLock<Target>>>,
-) -> anyhow::Result<std::collections::HashSet<String>> {
+) -> Result<std::collections::HashSet<String>> {
     if let Some(commits) = commits_by_target.get(target_name) {
         return Ok(commits.clone());
     }
     let mut all_commits: std::collections::HashSet<String> = std::collections::HashSet::new();
-    let rule = deps_graph.rules_by_label.get(target_name).ok_or(anyhow!(
-        "target {} not found in dependency graph",
-        target_name
-    ))?;
+    let rule = deps_graph.get_rule(target_name)?;
     let target_rc = Rc::new(RwLock::new(Target {
         name: target_name.to_string(),
         rebuilds: 0,
         immediate_dependents: vec![],
     }));
+    // registered before visiting the deps, so that a cycle back to it can
+    // be told apart from a target that is done.
+    score_by_target.insert(target_name.to_string(), target_rc.clone());
     for dep_target in rule.dep_targets.iter() {
+        if score_by_target.contains_key(dep_target) && !commits_by_target.contains_key(dep_target) {
+            warn!(
+                "{} depends on {} through a cycle, ignoring that dependency",
+                target_name, dep_target
+            );
+            continue;
+        }
         all_commits.extend(calculate_trigger_scores_map_inner(
             dep_target,
             repo,
@@ -145,20 +224,17 @@ fn calculate_trigger_scores_map_inner(
     let mut commits_touching_files = HashSet::new();
     for source_file in rule.source_files.iter() {
         // we don't care about remote dependencies
-        if source_file.starts_with("@") {
+        let Some(relative_path) = bazel::label_to_path(source_file) else {
             continue;
-        }
-        let parts: Vec<&str> = source_file.split(':').collect();
-        let relative_path = &format!("{}/{}", parts[0], parts[1])[2..];
+        };
 
-        if let Some(file) = repo.files.get(relative_path) {
+        if let Some(file) = repo.files.get(&relative_path) {
             commits_touching_files.extend(file.commit_history.iter().cloned());
         }
     }
     all_commits.extend(commits_touching_files.iter().cloned());
     let mut target = target_rc.write().unwrap();
     target.rebuilds = all_commits.len();
-    score_by_target.insert(target_name.to_string(), target_rc.clone());
     commits_by_target.insert(target_name.to_string(), all_commits.clone());
     commits_specific_to_target.insert(target_name.to_string(), commits_touching_files);
     Ok(all_commits)
@@ -184,3 +260,224 @@ fn inner_recursively_calculate_total_dependents(
     }
     total
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::DepsawError;
+    use crate::testing::{graph, repo, rule, source, TempDir};
+
+    #[test]
+    fn bogus_target_is_not_found() {
+        let deps_graph = graph(&[rule("//a:lib", "java_library", &[])]);
+        let err = calculate_trigger_scores(&["//nope:lib".to_string()], &repo(&[]), &deps_graph)
+            .unwrap_err();
+        assert!(matches!(err, DepsawError::TargetNotFound(label) if label == "//nope:lib"));
+    }
+
+    #[test]
+    fn scores_are_zero_without_commits() {
+        let deps_graph = graph(&[
+            rule("//app:bin", "java_binary", &["//a:lib", "//app:Main.java"]),
+            rule("//a:lib", "java_library", &["//a:A.java"]),
+            source("//app:Main.java"),
+            source("//a:A.java"),
+        ]);
+        let scores_by_target =
+            calculate_trigger_scores(&["//app:bin".to_string()], &repo(&[]), &deps_graph).unwrap();
+        assert_eq!(scores_by_target.len(), 2);
+        for target in scores_by_target.values() {
+            assert_eq!((target.rebuilds, target.score), (0, 0), "{}", target.name);
+            assert!(target.commits.is_empty());
+        }
+    }
+
+    #[test]
+    fn target_without_deps_has_a_single_row() {
+        let deps_graph = graph(&[rule("//a:lib", "java_library", &[])]);
+        for git_repo in [repo(&[]), repo(&[("a/A.java", &["c1"])])] {
+            let scores_by_target =
+                calculate_trigger_scores(&["//a:lib".to_string()], &git_repo, &deps_graph).unwrap();
+            assert_eq!(scores_by_target.len(), 1);
+            let target = &scores_by_target["//a:lib"];
+            assert_eq!(
+                (target.rebuilds, target.total_dependents, target.score),
+                (0, 0, 0)
+            );
+        }
+    }
+
+    #[test]
+    fn the_edge_closing_a_cycle_is_ignored() {
+        let deps_graph = graph(&[
+            rule("//app:bin", "java_binary", &["//a:lib"]),
+            rule("//a:lib", "java_library", &["//a:A.java", "//b:lib"]),
+            rule("//b:lib", "java_library", &["//b:B.java", "//a:lib"]),
+            source("//a:A.java"),
+            source("//b:B.java"),
+        ]);
+        let git_repo = repo(&[("a/A.java", &["c1"]), ("b/B.java", &["c2"])]);
+        let scores_by_target =
+            calculate_trigger_scores(&["//app:bin".to_string()], &git_repo, &deps_graph).unwrap();
+        let rebuilds = |label: &str| scores_by_target[label].rebuilds;
+        assert_eq!(
+            (
+                rebuilds("//app:bin"),
+                rebuilds("//a:lib"),
+                rebuilds("//b:lib")
+            ),
+            (2, 2, 1)
+        );
+        assert_eq!(scores_by_target["//b:lib"].total_dependents, 2);
+    }
+
+    #[test]
+    fn results_do_not_depend_on_the_order_of_the_targets() {
+        let deps_graph = graph(&[
+            rule("//app:bin", "java_binary", &["//a:lib"]),
+            rule("//server:bin", "java_binary", &["//a:lib"]),
+            rule("//a:lib", "java_library", &["//a:A.java"]),
+            source("//a:A.java"),
+        ]);
+        let git_repo = repo(&[("a/A.java", &["c1", "c2"])]);
+        let scores = |targets: &[&str]| {
+            let targets: Vec<String> = targets.iter().map(|t| t.to_string()).collect();
+            calculate_trigger_scores(&targets, &git_repo, &deps_graph).unwrap()
+        };
+        let forward = scores(&["//app:bin", "//server:bin"]);
+        assert_eq!(forward, scores(&["//server:bin", "//app:bin"]));
+        assert_eq!(
+            forward["//a:lib"].triggering_roots,
+            vec!["//app:bin", "//server:bin"]
+        );
+    }
+
+    #[test]
+    fn lines_of_code_are_counted_in_the_workspace() {
+        let workspace_dir = TempDir::new("loc");
+        workspace_dir.write("a/A.java", "1\n2\n3\n4\n");
+        workspace_dir.write("a/B.java", "1\n2\n3\n4\n5\n6\n7\n8\n");
+        let deps_graph = graph(&[
+            rule("//a:lib", "java_library", &["//a:A.java", "//a:B.java"]),
+            rule("//b:lib", "java_library", &["//b:Missing.java"]),
+            source("//a:A.java"),
+            source("//a:B.java"),
+            source("//b:Missing.java"),
+        ]);
+        let git_repo = repo(&[("a/A.java", &["c1", "c2", "c3"])]);
+        let targets = ["//a:lib".to_string(), "//b:lib".to_string()];
+        let mut scores_by_target =
+            calculate_trigger_scores(&targets, &git_repo, &deps_graph).unwrap();
+        let mut workspace = Workspace::new(workspace_dir.path_str(), false);
+        let stats = add_lines_of_code(&mut scores_by_target, &mut workspace, &deps_graph).unwrap();
+
+        let a = &scores_by_target["//a:lib"];
+        assert_eq!(a.total_lines, Some(12));
+        assert_eq!(a.rebuilds_per_kloc, Some(3.0 * 1000.0 / 12.0));
+        let b = &scores_by_target["//b:lib"];
+        assert_eq!(b.total_lines, Some(0));
+        assert_eq!(b.rebuilds_per_kloc, None);
+        assert_eq!(stats.files_counted, 2);
+        assert_eq!(stats.files_missing, 1);
+        assert!(stats.skipped_paths.is_empty());
+    }
+
+    #[test]
+    fn lines_of_code_are_only_serialized_when_counted() {
+        let deps_graph = graph(&[rule("//a:lib", "java_library", &[])]);
+        let mut scores_by_target =
+            calculate_trigger_scores(&["//a:lib".to_string()], &repo(&[]), &deps_graph).unwrap();
+        let json = serde_json::to_string(&scores_by_target["//a:lib"]).unwrap();
+        assert!(!json.contains("total_lines"));
+        assert!(!json.contains("rebuilds_per_kloc"));
+
+        scores_by_target.get_mut("//a:lib").unwrap().total_lines = Some(0);
+        let json = serde_json::to_string(&scores_by_target["//a:lib"]).unwrap();
+        assert!(json.contains("\"total_lines\":0"));
+        assert!(!json.contains("rebuilds_per_kloc"));
+    }
+
+    fn csv_row(target: &ResolvedTarget) -> String {
+        let mut wtr = csv::WriterBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_writer(vec![]);
+        wtr.serialize(target).unwrap();
+        String::from_utf8(wtr.into_inner().unwrap()).unwrap()
+    }
+
+    /// CSV output has no header, so the columns of existing fields must stay
+    /// where they are, with new fields after them.
+    #[test]
+    fn csv_columns_keep_their_positions() {
+        let deps_graph = graph(&[
+            rule("//app:bin", "java_binary", &["//a:lib", "@maven//:guava"]),
+            rule("//a:lib", "java_library", &["//a:A.java"]),
+            source("//a:A.java"),
+        ]);
+        let git_repo = repo(&[("a/A.java", &["c1", "c2"])]);
+        let scores_by_target =
+            calculate_trigger_scores(&["//app:bin".to_string()], &git_repo, &deps_graph).unwrap();
+        assert_eq!(
+            csv_row(&scores_by_target["//a:lib"]),
+            "//a:lib,2,1,1,4,0,,\n"
+        );
+
+        let mut target = scores_by_target["//app:bin"].clone();
+        target.total_lines = Some(500);
+        target.rebuilds_per_kloc = Some(4.0);
+        target.triggering_roots = vec!["//app:bin".to_string(), "//other:bin".to_string()];
+        assert_eq!(
+            csv_row(&target),
+            "//app:bin,2,0,0,2,500,4.0,1,,,//app:bin,//other:bin\n"
+        );
+    }
+
+    #[test]
+    fn costs_weight_scores_with_a_default() {
+        let deps_graph = graph(&[
+            rule("//app:bin", "java_binary", &["//a:lib", "//b:lib"]),
+            rule("//a:lib", "java_library", &["//a:A.java"]),
+            rule("//b:lib", "java_library", &["//b:B.java"]),
+            source("//a:A.java"),
+            source("//b:B.java"),
+        ]);
+        let git_repo = repo(&[("a/A.java", &["c1", "c2"]), ("b/B.java", &["c3"])]);
+        let costs: HashMap<String, f64> = [
+            ("//a:lib".to_string(), 2.5),
+            ("//gone:lib".to_string(), 1.0),
+        ]
+        .into_iter()
+        .collect();
+        let mut scores_by_target =
+            calculate_trigger_scores(&["//app:bin".to_string()], &git_repo, &deps_graph).unwrap();
+
+        add_costs(&mut scores_by_target, &costs, None, &deps_graph);
+        let weights = |scores: &HashMap<String, ResolvedTarget>, name: &str| {
+            (scores[name].cost, scores[name].weighted_score)
+        };
+        assert_eq!(
+            weights(&scores_by_target, "//a:lib"),
+            (Some(2.5), Some(10.0))
+        );
+        assert_eq!(weights(&scores_by_target, "//b:lib"), (None, None));
+
+        add_costs(&mut scores_by_target, &costs, Some(0.5), &deps_graph);
+        assert_eq!(
+            weights(&scores_by_target, "//a:lib"),
+            (Some(2.5), Some(10.0))
+        );
+        assert_eq!(
+            weights(&scores_by_target, "//b:lib"),
+            (Some(0.5), Some(1.0))
+        );
+        assert_eq!(
+            weights(&scores_by_target, "//app:bin"),
+            (Some(0.5), Some(1.5))
+        );
+
+        // unknown labels are warned about, and otherwise ignored.
+        assert_eq!(unknown_labels(&costs, &deps_graph), vec!["//gone:lib"]);
+        assert!(!scores_by_target.contains_key("//gone:lib"));
+    }
+}
@@ -0,0 +1,251 @@
+//! Reading and writing precalculated analysis files.
+//!
+//! rkyv archives start with a header, so that files written by an
+//! incompatible depsaw version are rejected with guidance instead of being
+//! misread:
+//!
+//! - the magic bytes `DEPSAW`.
+//! - the kind of analysis, as a byte.
+//! - the schema version, as a little endian u32.
+//! - the version of depsaw that wrote the file, as a little endian u16
+//!   length followed by that many bytes of UTF-8.
+use super::error::{DepsawError, Result};
+use super::metrics;
+use rkyv::util::AlignedVec;
+use tracing::debug;
+
+const MAGIC: &[u8] = b"DEPSAW";
+
+/// the version of the archived structs. Bump this whenever `GitRepo`,
+/// `BazelDependencyGraph` or anything they contain changes layout.
+pub const SCHEMA_VERSION: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    GitRepo,
+    BazelGraph,
+}
+
+impl Kind {
+    fn tag(self) -> u8 {
+        match self {
+            Kind::GitRepo => 1,
+            Kind::BazelGraph => 2,
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Kind::GitRepo => "a git analysis",
+            Kind::BazelGraph => "a bazel dependency graph",
+        }
+    }
+}
+
+pub fn read(path: &str) -> std::io::Result<Vec<u8>> {
+    let content = std::fs::read(path)?;
+    debug!("read {} bytes from {}", content.len(), path);
+    metrics::record_bytes_read(content.len());
+    Ok(content)
+}
+
+pub fn write(path: &str, content: &[u8]) -> std::io::Result<()> {
+    std::fs::write(path, content)?;
+    debug!("wrote {} bytes to {}", content.len(), path);
+    metrics::record_bytes_written(content.len());
+    Ok(())
+}
+
+/// write an rkyv archive, preceded by the header.
+pub fn write_archive(path: &str, kind: Kind, archive: &[u8]) -> Result<()> {
+    let version = env!("CARGO_PKG_VERSION").as_bytes();
+    let mut content = Vec::with_capacity(MAGIC.len() + 7 + version.len() + archive.len());
+    content.extend_from_slice(MAGIC);
+    content.push(kind.tag());
+    content.extend_from_slice(&SCHEMA_VERSION.to_le_bytes());
+    content.extend_from_slice(&(version.len() as u16).to_le_bytes());
+    content.extend_from_slice(version);
+    content.extend_from_slice(archive);
+    write(path, &content)?;
+    Ok(())
+}
+
+/// the error for an archive that passed the header check but failed rkyv's
+/// validation, e.g. because the file was truncated.
+pub fn corrupted(path: &str, e: rkyv::rancor::Error) -> DepsawError {
+    DepsawError::SerializationError(format!(
+        "{} is corrupted or truncated ({}); re-run precalculate to regenerate it",
+        path, e
+    ))
+}
+
+/// read an rkyv archive, after checking that its header matches the kind
+/// and schema version this binary expects. The archive is copied into an
+/// aligned buffer, as rkyv requires.
+pub fn read_archive(path: &str, kind: Kind) -> Result<AlignedVec> {
+    let content = read(path)?;
+    let incompatible = |reason: String| {
+        DepsawError::SerializationError(format!(
+            "{} {}; re-run precalculate to regenerate it",
+            path, reason
+        ))
+    };
+    let Some(rest) = content.strip_prefix(MAGIC) else {
+        return Err(incompatible(
+            "is not a depsaw analysis file, or was written by a depsaw version from before analysis files were versioned".to_string(),
+        ));
+    };
+    let truncated = || incompatible("has a truncated header".to_string());
+    let (&tag, rest) = rest.split_first().ok_or_else(truncated)?;
+    let (schema, rest) = rest.split_first_chunk::<4>().ok_or_else(truncated)?;
+    let schema = u32::from_le_bytes(*schema);
+    let (version_len, rest) = rest.split_first_chunk::<2>().ok_or_else(truncated)?;
+    let version_len = u16::from_le_bytes(*version_len) as usize;
+    if rest.len() < version_len {
+        return Err(truncated());
+    }
+    let (version, archive) = rest.split_at(version_len);
+    let version = String::from_utf8_lossy(version);
+
+    if tag != kind.tag() {
+        let actual = [Kind::GitRepo, Kind::BazelGraph]
+            .into_iter()
+            .find(|k| k.tag() == tag)
+            .map_or("an unknown kind of analysis", |k| k.describe());
+        return Err(DepsawError::SerializationError(format!(
+            "{} is {} file, expected {} file",
+            path,
+            actual,
+            kind.describe()
+        )));
+    }
+    if schema != SCHEMA_VERSION {
+        return Err(incompatible(format!(
+            "was written by depsaw {} (schema {}), this binary expects schema {}",
+            version, schema, SCHEMA_VERSION
+        )));
+    }
+    debug!("{} was written by depsaw {}", path, version);
+    let mut aligned = AlignedVec::with_capacity(archive.len());
+    aligned.extend_from_slice(archive);
+    Ok(aligned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::GitRepo;
+    use crate::testing::{graph, repo, rule, TempDir};
+
+    fn header(tag: u8, schema: u32, version: &str) -> Vec<u8> {
+        let mut content = MAGIC.to_vec();
+        content.push(tag);
+        content.extend_from_slice(&schema.to_le_bytes());
+        content.extend_from_slice(&(version.len() as u16).to_le_bytes());
+        content.extend_from_slice(version.as_bytes());
+        content
+    }
+
+    #[test]
+    fn archives_round_trip() {
+        let dir = TempDir::new("round-trip");
+        let git_path = dir.join("git.rkyv");
+        let mut git_repo = repo(&[("a/A.java", &["c2", "c1"])]);
+        git_repo.last_commit = Some("c2".to_string());
+        git_repo
+            .commit_times
+            .insert("c2".to_string(), 1_700_000_000);
+        git_repo.to_file(&git_path).unwrap();
+        let read = GitRepo::from_file(&git_path).unwrap();
+        assert_eq!(read.files, git_repo.files);
+        assert_eq!(read.last_commit, git_repo.last_commit);
+        assert_eq!(read.commit_times, git_repo.commit_times);
+        assert_eq!(read.window, git_repo.window);
+
+        let graph_path = dir.join("graph.rkyv");
+        let deps_graph = graph(&[rule("//a:lib", "java_library", &[])]);
+        deps_graph.to_file(&graph_path).unwrap();
+        let read = crate::bazel::BazelDependencyGraph::from_file(&graph_path).unwrap();
+        assert_eq!(
+            read.rules_by_label.keys().collect::<Vec<_>>(),
+            vec!["//a:lib"]
+        );
+
+        let header = header(
+            Kind::GitRepo.tag(),
+            SCHEMA_VERSION,
+            env!("CARGO_PKG_VERSION"),
+        );
+        assert!(std::fs::read(&git_path).unwrap().starts_with(&header));
+    }
+
+    #[test]
+    fn other_schema_versions_are_rejected() {
+        let dir = TempDir::new("schema");
+        let path = dir.join("git.rkyv");
+        let mut content = header(Kind::GitRepo.tag(), SCHEMA_VERSION - 1, "0.0.9");
+        content.extend_from_slice(b"an older layout");
+        std::fs::write(&path, content).unwrap();
+        let err = read_archive(&path, Kind::GitRepo).unwrap_err();
+        assert!(matches!(err, DepsawError::SerializationError(_)));
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "serialization error: {} was written by depsaw 0.0.9 (schema {}), this binary expects schema {}; re-run precalculate to regenerate it",
+                path,
+                SCHEMA_VERSION - 1,
+                SCHEMA_VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn other_kinds_and_headerless_files_are_rejected() {
+        let dir = TempDir::new("kind");
+        let path = dir.join("graph.rkyv");
+        std::fs::write(
+            &path,
+            header(Kind::BazelGraph.tag(), SCHEMA_VERSION, "0.1.0"),
+        )
+        .unwrap();
+        let err = read_archive(&path, Kind::GitRepo).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("is a bazel dependency graph file, expected a git analysis file"));
+
+        std::fs::write(&path, b"\x00\x01\x02 an old headerless archive").unwrap();
+        let err = read_archive(&path, Kind::GitRepo).unwrap_err();
+        assert!(err.to_string().contains("is not a depsaw analysis file"));
+    }
+
+    #[test]
+    fn truncated_files_are_errors() {
+        let dir = TempDir::new("truncated");
+        let path = dir.join("git.rkyv");
+        let mut git_repo = repo(&[("a/A.java", &["c1"]), ("b/B.java", &["c1", "c2"])]);
+        git_repo.last_commit = Some("c2".to_string());
+        git_repo.to_file(&path).unwrap();
+        let content = std::fs::read(&path).unwrap();
+        let header_len = header(
+            Kind::GitRepo.tag(),
+            SCHEMA_VERSION,
+            env!("CARGO_PKG_VERSION"),
+        )
+        .len();
+
+        // every truncation is an error, and never a panic or a misread.
+        for len in 0..content.len() {
+            std::fs::write(&path, &content[..len]).unwrap();
+            let err = GitRepo::from_file(&path).unwrap_err();
+            assert!(matches!(err, DepsawError::SerializationError(_)), "{}", len);
+            let expected = if len < MAGIC.len() {
+                "is not a depsaw analysis file"
+            } else if len < header_len {
+                "has a truncated header"
+            } else {
+                "is corrupted or truncated"
+            };
+            assert!(err.to_string().contains(expected), "{}: {}", len, err);
+        }
+    }
+}
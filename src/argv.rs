@@ -0,0 +1,163 @@
+//! Keeping command lines within the operating system's limits.
+//!
+//! Long lists of paths or labels overflow the maximum argument size
+//! (`E2BIG` on Linux, and much sooner on Windows). Call sites passing lists
+//! to a subprocess check the size of their arguments here, and fall back to
+//! the tool's own file based input when they are too large: `git log
+//! --stdin` for pathspecs, `bazel query --query_file` for queries, and
+//! `buildozer -f` for buildozer commands. None of these limit the size of
+//! their input, so the file based route never has to be split up.
+//!
+//! The limit is a parameter of the functions building command lines, so
+//! tests can force the fallback with a tiny one.
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::debug;
+
+/// the largest command line passed directly to a subprocess, in bytes. This
+/// is well below Linux's limit, to leave room for the environment and stay
+/// under Windows' 32 KiB limit.
+pub const MAX_ARGV_BYTES: usize = 24 * 1024;
+
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// the size of the arguments on a command line, counting the separator
+/// after each.
+pub fn argv_size(prog: &str, args: &[String]) -> usize {
+    prog.len() + 1 + args.iter().map(|a| a.len() + 1).sum::<usize>()
+}
+
+/// true if the command line is too long to pass directly.
+pub fn exceeds_limit(prog: &str, args: &[String], limit: usize) -> bool {
+    let size = argv_size(prog, args);
+    if size > limit {
+        debug!(
+            "{} arguments are {} bytes, over the limit of {}",
+            prog, size, limit
+        );
+        return true;
+    }
+    false
+}
+
+/// A command line, and the file holding the input that didn't fit on it.
+pub struct Invocation {
+    pub args: Vec<String>,
+    /// passed by path in `args`, so it must outlive the command.
+    pub input_file: Option<TempFile>,
+}
+
+/// the arguments when they are within the limit. Otherwise, `contents` is
+/// written to a temporary file, and the arguments are `fallback`'s for
+/// reading it from its path.
+pub fn args_or_file(
+    prog: &str,
+    args: Vec<String>,
+    limit: usize,
+    file_name: &str,
+    contents: impl FnOnce() -> String,
+    fallback: impl FnOnce(&Path) -> Vec<String>,
+) -> std::io::Result<Invocation> {
+    if !exceeds_limit(prog, &args, limit) {
+        return Ok(Invocation {
+            args,
+            input_file: None,
+        });
+    }
+    let input_file = TempFile::new(file_name, &contents())?;
+    Ok(Invocation {
+        args: fallback(input_file.path()),
+        input_file: Some(input_file),
+    })
+}
+
+/// a file in the temporary directory, removed when dropped. Used to pass
+/// input to tools that read it from a file.
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    pub fn new(name: &str, contents: &str) -> std::io::Result<TempFile> {
+        let path = std::env::temp_dir().join(format!(
+            "depsaw-{}-{}-{}",
+            std::process::id(),
+            TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed),
+            name
+        ));
+        std::fs::write(&path, contents)?;
+        Ok(TempFile { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            debug!("unable to remove {:?}: {}", self.path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn size_counts_a_separator_after_each_argument() {
+        assert_eq!(argv_size("git", &[]), 4);
+        assert_eq!(argv_size("git", &args(&["log", "--"])), 11);
+    }
+
+    #[test]
+    fn limit_is_inclusive() {
+        let log = args(&["log", "--"]);
+        assert!(!exceeds_limit("git", &log, 11));
+        assert!(exceeds_limit("git", &log, 10));
+        assert!(!exceeds_limit("git", &log, MAX_ARGV_BYTES));
+    }
+
+    #[test]
+    fn short_arguments_are_passed_directly() {
+        let invocation = args_or_file(
+            "bazel",
+            args(&["query", "deps(//a:b)"]),
+            MAX_ARGV_BYTES,
+            "query.txt",
+            || unreachable!("the file isn't written"),
+            |_| unreachable!("the arguments fit"),
+        )
+        .unwrap();
+        assert_eq!(invocation.args, args(&["query", "deps(//a:b)"]));
+        assert!(invocation.input_file.is_none());
+    }
+
+    #[test]
+    fn long_arguments_are_passed_in_a_file() {
+        let invocation = args_or_file(
+            "bazel",
+            args(&["query", "deps(//a:b)"]),
+            8,
+            "query.txt",
+            || "deps(//a:b)".to_string(),
+            |path| vec![format!("--query_file={}", path.display())],
+        )
+        .unwrap();
+        let file = invocation.input_file.as_ref().unwrap();
+        let path = file.path().to_path_buf();
+        assert_eq!(
+            invocation.args,
+            vec![format!("--query_file={}", path.display())]
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "deps(//a:b)");
+        drop(invocation);
+        assert!(!path.exists());
+    }
+}
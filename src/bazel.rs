@@ -1,63 +1,174 @@
-use anyhow;
-use rkyv;
+use super::error::{DepsawError, Result};
+use super::{analysis_file, argv, input, metrics};
 use rkyv::{Archive, Deserialize as RkyvDeserialize, Serialize as RkyvSerialize};
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
-use std::error::Error;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::BufRead;
 use std::process::Command;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
+/// How often an external repository is referred to in the graph.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExternalRepoReferences {
+    pub repo: String,
+    pub references: usize,
+}
+
+/// Options for parsing bazel query output into a graph.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParseOptions {
+    /// error if any line of the query output fails to parse.
+    pub strict: bool,
+    /// keep `dep_targets` and `source_files` in bazel's ruleInput order, and
+    /// `exports` and `visibility` in attribute order, instead of sorting
+    /// them.
+    pub preserve_input_order: bool,
+}
+
+/// The dependency graph. Rules are keyed in a sorted map so that the same
+/// logical graph always serializes to the same bytes.
 #[derive(Archive, Debug, RkyvSerialize, RkyvDeserialize, Clone)]
 pub struct BazelDependencyGraph {
-    pub rules_by_label: HashMap<String, Entry>,
+    pub rules_by_label: BTreeMap<String, Entry>,
+    /// the number of rule inputs referring to each external repository,
+    /// e.g. `@maven`. External inputs are left out of the graph, since they
+    /// have no git history, but are counted to show third party exposure.
+    pub external_references_by_repo: BTreeMap<String, usize>,
 }
 
+/// A rule in the graph.
+///
+/// Generated inputs are replaced by the rule generating them in
+/// `dep_targets`, so that changes to that rule's sources reach this one.
+///
+/// `dep_targets`, `source_files`, `exports` and `visibility` are sorted
+/// lexicographically when the graph is built, since bazel's ordering changes
+/// between bazel versions. Consumers can rely on this order, unless the
+/// graph was built with `ParseOptions::preserve_input_order`.
 #[derive(Archive, Debug, RkyvSerialize, RkyvDeserialize, Clone)]
 pub struct Entry {
+    pub rule_class: String,
     pub dep_targets: Vec<String>,
     pub source_files: Vec<String>,
+    /// labels listed in the rule's `exports` attribute, if any.
+    pub exports: Vec<String>,
+    /// the rule's `visibility` attribute. bazel reports the package's
+    /// default visibility for rules that don't set their own. Empty if
+    /// unknown.
+    pub visibility: Vec<String>,
+    /// the number of external inputs, e.g. `@maven//:guava`, left out of
+    /// `dep_targets` and `source_files`.
+    pub external_dep_count: usize,
+}
+
+/// the arguments of the query for the dependencies of the targets. A single
+/// query over the union of the targets is run, so that shared dependencies
+/// are only loaded once. Long queries are passed through a file instead.
+fn query_invocation(prog: &str, targets: &[String], limit: usize) -> Result<argv::Invocation> {
+    let query = format!("deps({})", targets.join(" union "));
+    let args = |query: String| -> Vec<String> {
+        vec![
+            "query".to_string(),
+            query,
+            "--output".to_string(),
+            "streamed_jsonproto".to_string(),
+        ]
+    };
+    Ok(argv::args_or_file(
+        prog,
+        args(query.clone()),
+        limit,
+        "query.txt",
+        || query.clone(),
+        |path| args(format!("--query_file={}", path.display())),
+    )?)
 }
 
 impl BazelDependencyGraph {
-    pub fn from_file(path: &str) -> anyhow::Result<BazelDependencyGraph> {
+    pub fn from_file(path: &str) -> Result<BazelDependencyGraph> {
         info!("reading bazel dependency graph from {}", path);
-        let content = std::fs::read(path).unwrap();
-        Ok(rkyv::from_bytes::<BazelDependencyGraph, rkyv::rancor::Error>(&content)?)
+        let content = analysis_file::read_archive(path, analysis_file::Kind::BazelGraph)?;
+        rkyv::from_bytes::<BazelDependencyGraph, rkyv::rancor::Error>(&content)
+            .map_err(|e| analysis_file::corrupted(path, e))
+    }
+
+    pub fn to_file(&self, path: &str) -> Result<()> {
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(self)?;
+        analysis_file::write_archive(path, analysis_file::Kind::BazelGraph, &bytes)
     }
 
+    /// build the graph by running `bazel query` in the workspace.
     pub fn from_workspace(
         workspace_root: &str,
-        target: &str,
-    ) -> anyhow::Result<BazelDependencyGraph> {
+        targets: &[String],
+        options: ParseOptions,
+    ) -> Result<BazelDependencyGraph> {
         let prog = "bazel";
-        let cmd = format!(
-            "{} query 'deps({})' --output streamed_jsonproto",
-            prog, target
-        );
+        // the query file is removed once it goes out of scope, after bazel
+        // has run.
+        let argv::Invocation {
+            args,
+            input_file: _query_file,
+        } = query_invocation(prog, targets, argv::MAX_ARGV_BYTES)?;
+        let cmd = format!("{} {}", prog, args.join(" "));
         debug!(workspace_root, cmd, "running bazel query");
+        metrics::record_subprocess();
         let output = Command::new(prog)
             .current_dir(workspace_root)
-            .args([
-                "query",
-                &format!("deps({})", target),
-                "--output",
-                "streamed_jsonproto",
-            ])
-            .output()?;
+            .args(&args)
+            .output()
+            .map_err(|e| {
+                DepsawError::BazelQueryFailed(format!(
+                    "unable to run {} in {}: {}",
+                    cmd, workspace_root, e
+                ))
+            })?;
         if !output.status.success() {
-            return Err(anyhow::anyhow!(
-                "Bazel command {} failed: {}",
+            return Err(DepsawError::BazelQueryFailed(format!(
+                "{}: {}",
                 cmd,
-                String::from_utf8_lossy(&output.stderr)
-            ));
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
         }
         let content = String::from_utf8(output.stdout)?;
-        Ok(BazelDependencyGraph::from_string(&content))
+        BazelDependencyGraph::from_string(&content, options)
+    }
+
+    pub fn from_string(content: &str, options: ParseOptions) -> Result<BazelDependencyGraph> {
+        BazelDependencyGraph::from_reader(content.as_bytes(), options)
+    }
+
+    /// build the graph from saved `bazel query --output streamed_jsonproto`
+    /// output, read from a file, gzipped file, or stdin (`-`).
+    pub fn from_query_output(path: &str, options: ParseOptions) -> Result<BazelDependencyGraph> {
+        info!("reading bazel query output from {}", path);
+        BazelDependencyGraph::from_reader(input::open(path)?, options)
     }
 
-    pub fn from_string(content: &str) -> BazelDependencyGraph {
+    pub fn from_reader(
+        reader: impl BufRead,
+        options: ParseOptions,
+    ) -> Result<BazelDependencyGraph> {
         info!("parsing bazel dependency graph");
-        let raw_entries = read_from_protojson(content);
+        let (raw_entries, summary) = read_from_protojson(reader)?;
+        if summary.unknown_entries > 0 {
+            debug!(
+                "skipped {} entries of unsupported types",
+                summary.unknown_entries
+            );
+        }
+        if summary.failed_lines > 0 {
+            warn!(
+                "failed to parse {} of {} lines of bazel query output",
+                summary.failed_lines, summary.total_lines
+            );
+            if options.strict {
+                return Err(DepsawError::ParseError(format!(
+                    "failed to parse {} of {} lines of bazel query output",
+                    summary.failed_lines, summary.total_lines
+                )));
+            }
+        }
         let mut targets_by_label = HashMap::new();
         let mut rules = vec![];
         for entry in raw_entries {
@@ -69,17 +180,25 @@ impl BazelDependencyGraph {
                 DependencyEntry::SourceFile { source_file } => source_file.name.clone(),
                 DependencyEntry::PackageGroup { package_group } => package_group.name.clone(),
                 DependencyEntry::GeneratedFile { generated_file } => generated_file.name.clone(),
+                DependencyEntry::Unknown => continue,
             };
             targets_by_label.insert(name, entry);
         }
-        let mut rules_by_label = HashMap::new();
+        let mut rules_by_label = BTreeMap::new();
+        let mut external_references_by_repo = BTreeMap::new();
         // parse through each rule
         for rule in rules {
             let mut source_files = vec![];
             let mut dep_targets = vec![];
+            let mut external_dep_count = 0;
             for dep in rule.rule_input {
-                // ignore external dependencies
+                // leave external dependencies out of the graph, only
+                // counting them.
                 if dep.starts_with("@") {
+                    external_dep_count += 1;
+                    *external_references_by_repo
+                        .entry(external_repo(&dep).to_string())
+                        .or_insert(0) += 1;
                     continue;
                 }
                 if let Some(entry) = targets_by_label.get(&dep) {
@@ -90,26 +209,60 @@ impl BazelDependencyGraph {
                         DependencyEntry::Rule { rule } => {
                             dep_targets.push(rule.name.clone());
                         }
+                        // depend on the rule generating the file, so that
+                        // changes to its inputs trigger this rule. A rule
+                        // consuming its own outputs would be a cycle.
+                        DependencyEntry::GeneratedFile { .. } => {
+                            if let Some(generating_rule) =
+                                resolve_generating_rule(&dep, &targets_by_label)
+                            {
+                                if generating_rule != rule.name {
+                                    dep_targets.push(generating_rule);
+                                }
+                            }
+                        }
                         _ => {}
                     }
                 }
             }
+            let string_list = |name: &str| {
+                rule.attribute
+                    .iter()
+                    .find(|a| a.name == name)
+                    .and_then(|a| a.string_list_value.clone())
+                    .unwrap_or_default()
+            };
+            let mut exports = string_list("exports");
+            let mut visibility = string_list("visibility");
+            if !options.preserve_input_order {
+                dep_targets.sort();
+                source_files.sort();
+                exports.sort();
+                visibility.sort();
+            }
+            // a rule generating several of the inputs is only depended on
+            // once.
+            let mut seen = HashSet::new();
+            dep_targets.retain(|dep| seen.insert(dep.clone()));
             let entry = Entry {
+                rule_class: rule.rule_class,
                 dep_targets,
                 source_files,
+                exports,
+                visibility,
+                external_dep_count,
             };
             debug!("adding rule: {}", rule.name);
             rules_by_label.insert(rule.name, entry);
         }
 
-        BazelDependencyGraph { rules_by_label }
+        Ok(BazelDependencyGraph {
+            rules_by_label,
+            external_references_by_repo,
+        })
     }
 
-    pub fn get_source_files(
-        &self,
-        target: &str,
-        recursive: bool,
-    ) -> Result<Vec<String>, Box<dyn Error>> {
+    pub fn get_source_files(&self, target: &str, recursive: bool) -> Result<Vec<String>> {
         let mut visited_targets = HashSet::new();
         self.get_source_files_inner(target, recursive, &mut visited_targets)
     }
@@ -119,13 +272,13 @@ impl BazelDependencyGraph {
         target: &str,
         recursive: bool,
         visited_targets: &mut HashSet<String>,
-    ) -> Result<Vec<String>, Box<dyn Error>> {
+    ) -> Result<Vec<String>> {
         debug!("getting source files for {}", target);
-        let entry = self.rules_by_label.get(target).ok_or(format!(
-            "target {} not found in bazel dependency graph",
-            target
-        ))?;
+        let entry = self.get_rule(target)?;
         let mut source_files = entry.source_files.clone();
+        if !recursive {
+            return Ok(source_files);
+        }
         for dep_target in entry.dep_targets.iter() {
             if visited_targets.contains(dep_target) {
                 continue;
@@ -139,6 +292,289 @@ impl BazelDependencyGraph {
         visited_targets.insert(target.to_string());
         Ok(source_files)
     }
+
+    /// look up a rule, erroring if it isn't in the graph.
+    pub fn get_rule(&self, label: &str) -> Result<&Entry> {
+        self.rules_by_label
+            .get(label)
+            .ok_or_else(|| DepsawError::TargetNotFound(label.to_string()))
+    }
+
+    /// build a map of label to the labels that directly depend on it.
+    pub fn reverse_index(&self) -> HashMap<String, Vec<String>> {
+        let mut rdeps: HashMap<String, Vec<String>> = HashMap::new();
+        for (label, entry) in self.rules_by_label.iter() {
+            for dep in entry.dep_targets.iter() {
+                rdeps.entry(dep.clone()).or_default().push(label.clone());
+            }
+        }
+        rdeps
+    }
+
+    /// every target the given targets depend on transitively, including the
+    /// targets themselves.
+    pub fn transitive_deps(&self, targets: &[String]) -> HashSet<String> {
+        let mut visited = HashSet::new();
+        let mut stack = targets.to_vec();
+        while let Some(current) = stack.pop() {
+            if !visited.insert(current.clone()) {
+                continue;
+            }
+            if let Some(entry) = self.rules_by_label.get(&current) {
+                stack.extend(entry.dep_targets.iter().cloned());
+            }
+        }
+        visited
+    }
+
+    /// the labels in the graph matched by a target pattern. Anything that
+    /// isn't a wildcard pattern is returned as-is. Wildcards relative to a
+    /// package, such as `foo/...`, are rejected, as there is no working
+    /// directory to resolve them against.
+    pub fn expand_target_pattern(&self, pattern: &str) -> Result<Vec<String>> {
+        if !is_wildcard_pattern(pattern) {
+            return Ok(vec![pattern.to_string()]);
+        }
+        if is_relative_pattern(pattern) {
+            return Err(DepsawError::InvalidInput(format!(
+                "relative target pattern {} is not supported, use //{} instead",
+                pattern, pattern
+            )));
+        }
+        Ok(self
+            .rules_by_label
+            .keys()
+            .filter(|label| label_matches_pattern(label, pattern))
+            .cloned()
+            .collect())
+    }
+
+    /// the targets the rules matched by the pattern depend on, directly or
+    /// transitively, sorted. A rule is not its own dependency.
+    pub fn deps(&self, pattern: &str, transitive: bool) -> Result<Vec<String>> {
+        let mut deps = BTreeSet::new();
+        for label in self.expand_target_pattern(pattern)? {
+            let rule = self.get_rule(&label)?;
+            if transitive {
+                deps.extend(
+                    self.transitive_deps(std::slice::from_ref(&label))
+                        .into_iter()
+                        .filter(|dep| *dep != label),
+                );
+            } else {
+                deps.extend(rule.dep_targets.iter().cloned());
+            }
+        }
+        Ok(deps.into_iter().collect())
+    }
+
+    /// the targets depending on the rules matched by the pattern, directly
+    /// or transitively, sorted. A rule is not its own dependent.
+    pub fn rdeps(&self, pattern: &str, transitive: bool) -> Result<Vec<String>> {
+        let labels = self.expand_target_pattern(pattern)?;
+        for label in labels.iter() {
+            self.get_rule(label)?;
+        }
+        let mut rdeps = BTreeSet::new();
+        if transitive {
+            for label in labels {
+                rdeps.extend(
+                    self.transitive_rdeps(std::slice::from_ref(&label))
+                        .into_iter()
+                        .filter(|rdep| *rdep != label),
+                );
+            }
+        } else {
+            let reverse_index = self.reverse_index();
+            for label in labels {
+                rdeps.extend(reverse_index.get(&label).into_iter().flatten().cloned());
+            }
+        }
+        Ok(rdeps.into_iter().collect())
+    }
+
+    /// the targets listing the source file, given as a path relative to the
+    /// workspace root or as a label, sorted.
+    pub fn owners(&self, source_file: &str) -> Vec<String> {
+        let path = label_to_path(source_file).unwrap_or_else(|| source_file.to_string());
+        self.rules_by_label
+            .iter()
+            .filter(|(_, entry)| {
+                entry
+                    .source_files
+                    .iter()
+                    .any(|s| label_to_path(s).is_some_and(|p| p == path))
+            })
+            .map(|(label, _)| label.clone())
+            .collect()
+    }
+
+    /// whether the rule is visible outside of its own package, approximated
+    /// from its visibility attribute:
+    ///
+    /// - `//visibility:private` and the rule's own `__pkg__` are private.
+    /// - `//visibility:public`, other packages, and `__subpackages__` are
+    ///   public.
+    /// - package groups are treated as public, since what they contain isn't
+    ///   resolved.
+    /// - rules with unknown visibility, and labels missing from the graph,
+    ///   are treated as public.
+    pub fn is_public(&self, label: &str) -> bool {
+        let Some(entry) = self.rules_by_label.get(label) else {
+            return true;
+        };
+        if entry.visibility.is_empty() {
+            return true;
+        }
+        let package = label.split_once(':').map_or(label, |(package, _)| package);
+        entry
+            .visibility
+            .iter()
+            .any(|v| v != "//visibility:private" && *v != format!("{}:__pkg__", package))
+    }
+
+    /// return every target that transitively depends on any of the given
+    /// targets, including the targets themselves.
+    pub fn transitive_rdeps(&self, targets: &[String]) -> HashSet<String> {
+        let reverse_index = self.reverse_index();
+        let mut visited = HashSet::new();
+        let mut stack = targets.to_vec();
+        while let Some(current) = stack.pop() {
+            if !visited.insert(current.clone()) {
+                continue;
+            }
+            if let Some(dependents) = reverse_index.get(&current) {
+                stack.extend(dependents.iter().cloned());
+            }
+        }
+        visited
+    }
+
+    /// the external repositories referred to by rules in the graph, most
+    /// referenced first.
+    pub fn external_repo_summary(&self) -> Vec<ExternalRepoReferences> {
+        let mut summary: Vec<_> = self
+            .external_references_by_repo
+            .iter()
+            .map(|(repo, references)| ExternalRepoReferences {
+                repo: repo.clone(),
+                references: *references,
+            })
+            .collect();
+        summary.sort_by(|a, b| {
+            b.references
+                .cmp(&a.references)
+                .then_with(|| a.repo.cmp(&b.repo))
+        });
+        summary
+    }
+
+    /// build a map of source file path, relative to the workspace root, to
+    /// the rules that list it as a source.
+    pub fn targets_by_source_path(&self) -> HashMap<String, Vec<String>> {
+        let mut targets_by_path: HashMap<String, Vec<String>> = HashMap::new();
+        for (label, entry) in self.rules_by_label.iter() {
+            for source_file in entry.source_files.iter() {
+                if let Some(path) = label_to_path(source_file) {
+                    targets_by_path.entry(path).or_default().push(label.clone());
+                }
+            }
+        }
+        targets_by_path
+    }
+}
+
+/// the rule that generates a file, following generated files whose
+/// generating rule is itself reported as a generated file. None if the chain
+/// doesn't end at a rule in the query output, or loops.
+fn resolve_generating_rule(
+    label: &str,
+    targets_by_label: &HashMap<String, DependencyEntry>,
+) -> Option<String> {
+    let mut visited = HashSet::new();
+    let mut current = label.to_string();
+    loop {
+        if !visited.insert(current.clone()) {
+            warn!("generated file {} is part of a cycle, ignoring it", label);
+            return None;
+        }
+        match targets_by_label.get(&current)? {
+            DependencyEntry::Rule { rule } => return Some(rule.name.clone()),
+            DependencyEntry::GeneratedFile { generated_file } => {
+                current = generated_file.generating_rule.clone();
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// the repository of an external label, e.g. `@maven` for
+/// `@maven//:guava`.
+fn external_repo(label: &str) -> &str {
+    label.split_once("//").map_or(label, |(repo, _)| repo)
+}
+
+/// the wildcard suffixes that match every rule in a package.
+const ALL_RULES_SUFFIXES: [&str; 3] = [":all", ":*", ":all-targets"];
+
+fn is_wildcard_pattern(pattern: &str) -> bool {
+    pattern.ends_with("...") || ALL_RULES_SUFFIXES.iter().any(|s| pattern.ends_with(s))
+}
+
+/// whether a pattern is relative to a package, e.g. `foo/...` or `:all`.
+/// `...` alone means the whole workspace.
+fn is_relative_pattern(pattern: &str) -> bool {
+    let base = ALL_RULES_SUFFIXES
+        .iter()
+        .find_map(|suffix| pattern.strip_suffix(suffix))
+        .unwrap_or(pattern);
+    base != "..." && !pattern.starts_with("//") && !pattern.starts_with('@')
+}
+
+/// whether a label matches a bazel target pattern, following bazel's package
+/// semantics:
+///
+/// - `...` and `//...` match every label in the workspace.
+/// - `//foo/...` matches labels in the package `//foo` and its subpackages,
+///   but not `//foobar:lib`.
+/// - `//foo:all` (or `:*`, `:all-targets`) matches labels in exactly the
+///   package `//foo`.
+/// - anything else must equal the label.
+pub fn label_matches_pattern(label: &str, pattern: &str) -> bool {
+    if label == pattern {
+        return true;
+    }
+    let package = label.split_once(':').map_or(label, |(package, _)| package);
+    // `//foo/...:all` means the same as `//foo/...`.
+    let (pattern, all_rules) = match ALL_RULES_SUFFIXES
+        .iter()
+        .find_map(|suffix| pattern.strip_suffix(suffix))
+    {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+    if pattern == "..." || pattern == "//..." {
+        return label.starts_with("//");
+    }
+    if let Some(base) = pattern.strip_suffix("/...") {
+        return package == base
+            || package
+                .strip_prefix(base)
+                .is_some_and(|rest| rest.starts_with('/'));
+    }
+    all_rules && package == pattern
+}
+
+/// convert a source file label such as `//foo/bar:baz.rs` into its path
+/// relative to the workspace root (`foo/bar/baz.rs`). Returns None for
+/// labels in external repositories.
+pub fn label_to_path(label: &str) -> Option<String> {
+    let label = label.strip_prefix("//")?;
+    match label.split_once(':') {
+        Some(("", name)) => Some(name.to_string()),
+        Some((package, name)) => Some(format!("{}/{}", package, name)),
+        None => Some(label.to_string()),
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -161,6 +597,9 @@ enum DependencyEntry {
         #[serde(rename = "generatedFile")]
         generated_file: GeneratedFile,
     },
+    /// entry types we don't use, such as ENVIRONMENT_GROUP.
+    #[serde(other)]
+    Unknown,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -168,7 +607,9 @@ struct Rule {
     pub name: String,
     #[serde(rename = "ruleClass")]
     pub rule_class: String,
+    #[serde(default)]
     pub location: String,
+    #[serde(default)]
     pub attribute: Vec<Attribute>,
     #[serde(default, rename = "ruleInput")]
     pub rule_input: Vec<String>,
@@ -179,7 +620,7 @@ struct Rule {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct Attribute {
     pub name: String,
-    #[serde(rename = "type")]
+    #[serde(default, rename = "type")]
     pub attr_type: String,
     #[serde(rename = "stringValue")]
     pub string_value: Option<String>,
@@ -197,8 +638,9 @@ struct Attribute {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SourceFile {
     pub name: String,
+    #[serde(default)]
     pub location: String,
-    #[serde(rename = "visibilityLabel")]
+    #[serde(default, rename = "visibilityLabel")]
     pub visibility_label: Vec<String>,
 }
 
@@ -212,22 +654,518 @@ struct GeneratedFile {
     pub name: String,
     #[serde(rename = "generatingRule")]
     pub generating_rule: String,
+    #[serde(default)]
     pub location: String,
 }
 
+#[derive(Debug, Default)]
+struct ParseSummary {
+    total_lines: usize,
+    failed_lines: usize,
+    unknown_entries: usize,
+}
+
 // read the contents of a bazel protojson file and parse it into a vector of DependencyEntry
 // this is generated via `bazel query "deps(//...)" --output streamed_jsonproto`
-fn read_from_protojson(content: &str) -> Vec<DependencyEntry> {
-    content
-        .lines()
-        .filter(|line| !line.trim().is_empty())
-        .filter_map(|line| {
-            serde_json::from_str(line)
-                .map_err(|e| {
-                    eprintln!("Failed to parse line: {}", e);
+//
+// lines that fail to parse are skipped and counted in the returned summary.
+fn read_from_protojson(reader: impl BufRead) -> Result<(Vec<DependencyEntry>, ParseSummary)> {
+    let mut summary = ParseSummary::default();
+    let mut entries = vec![];
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        summary.total_lines += 1;
+        match serde_json::from_str::<DependencyEntry>(&line) {
+            Ok(DependencyEntry::Unknown) => summary.unknown_entries += 1,
+            Ok(entry) => entries.push(entry),
+            Err(e) => {
+                warn!(
+                    "failed to parse line {} of bazel query output: {}",
+                    i + 1,
                     e
-                })
-                .ok()
-        })
-        .collect()
+                );
+                summary.failed_lines += 1;
+            }
+        }
+    }
+    Ok((entries, summary))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{generated, graph, rule, rule_with_attributes, source};
+
+    const EXAMPLE: &str = include_str!("../examples/dependencies.ndjson");
+
+    fn with_lines(lines: &[&str]) -> String {
+        format!("{}{}\n", EXAMPLE, lines.join("\n"))
+    }
+
+    #[test]
+    fn parses_example_query_output() {
+        let options = ParseOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let graph = BazelDependencyGraph::from_string(EXAMPLE, options).unwrap();
+        assert_eq!(graph.rules_by_label.len(), 71);
+        let runner = graph.get_rule("//:runner").unwrap();
+        assert_eq!(runner.rule_class, "java_binary");
+        assert_eq!(
+            runner.dep_targets,
+            vec!["//src/main/java/com/example/restaurant:cafe"]
+        );
+        assert_eq!(
+            runner.source_files,
+            vec!["//:src/main/java/com/example/Runner.java"]
+        );
+        assert_eq!(runner.external_dep_count, 8);
+        assert!(graph.external_references_by_repo["@bazel_tools"] >= 8);
+    }
+
+    #[test]
+    fn bad_lines_are_skipped_unless_strict() {
+        let content = with_lines(&["{\"type\": \"RULE\", \"rule\": {", "not json"]);
+        let graph = BazelDependencyGraph::from_string(&content, ParseOptions::default()).unwrap();
+        assert_eq!(graph.rules_by_label.len(), 71);
+
+        let strict = ParseOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let err = BazelDependencyGraph::from_string(&content, strict).unwrap_err();
+        assert!(matches!(err, DepsawError::ParseError(_)));
+        assert!(err.to_string().contains("failed to parse 2 of 145 lines"));
+    }
+
+    #[test]
+    fn unknown_entry_types_are_skipped_even_if_strict() {
+        let content = with_lines(&[
+            "{\"type\": \"ENVIRONMENT_GROUP\", \"environmentGroup\": {\"name\": \"//:env\"}}",
+            "",
+        ]);
+        for strict in [false, true] {
+            let options = ParseOptions {
+                strict,
+                ..Default::default()
+            };
+            let graph = BazelDependencyGraph::from_string(&content, options).unwrap();
+            assert_eq!(graph.rules_by_label.len(), 71);
+            assert!(graph.get_rule("//:env").is_err());
+        }
+    }
+
+    fn archive(lines: &[String], options: ParseOptions) -> Vec<u8> {
+        let graph = BazelDependencyGraph::from_string(&lines.join("\n"), options).unwrap();
+        rkyv::to_bytes::<rkyv::rancor::Error>(&graph)
+            .unwrap()
+            .to_vec()
+    }
+
+    #[test]
+    fn shuffled_inputs_serialize_to_identical_bytes() {
+        let lines = vec![
+            rule_with_attributes(
+                "//a:lib",
+                &[
+                    "//a:A.java",
+                    "//a:B.java",
+                    "//b:lib",
+                    "//c:lib",
+                    "@maven//:guava",
+                ],
+                &["//b:lib", "//c:lib"],
+                &["//x:__pkg__", "//y:__pkg__"],
+            ),
+            rule_with_attributes("//b:lib", &[], &[], &[]),
+            rule_with_attributes("//c:lib", &[], &[], &[]),
+            source("//a:A.java"),
+            source("//a:B.java"),
+        ];
+        let shuffled = vec![
+            source("//a:B.java"),
+            rule_with_attributes("//c:lib", &[], &[], &[]),
+            rule_with_attributes(
+                "//a:lib",
+                &[
+                    "@maven//:guava",
+                    "//c:lib",
+                    "//a:B.java",
+                    "//b:lib",
+                    "//a:A.java",
+                ],
+                &["//c:lib", "//b:lib"],
+                &["//y:__pkg__", "//x:__pkg__"],
+            ),
+            source("//a:A.java"),
+            rule_with_attributes("//b:lib", &[], &[], &[]),
+        ];
+        let options = ParseOptions::default();
+        assert_eq!(archive(&lines, options), archive(&shuffled, options));
+
+        let preserved = ParseOptions {
+            preserve_input_order: true,
+            ..Default::default()
+        };
+        assert_ne!(archive(&lines, preserved), archive(&shuffled, preserved));
+        let graph = BazelDependencyGraph::from_string(&shuffled.join("\n"), preserved).unwrap();
+        let entry = graph.get_rule("//a:lib").unwrap();
+        assert_eq!(entry.dep_targets, vec!["//c:lib", "//b:lib"]);
+        assert_eq!(entry.exports, vec!["//c:lib", "//b:lib"]);
+        assert_eq!(entry.visibility, vec!["//y:__pkg__", "//x:__pkg__"]);
+    }
+
+    #[test]
+    fn visibility_is_classified_conservatively() {
+        let cases: [(&[&str], bool); 9] = [
+            (&["//visibility:public"], true),
+            (&["//visibility:private"], false),
+            (&["//app/impl:__pkg__"], false),
+            (&["//app:__pkg__"], true),
+            (&["//app/impl:__subpackages__"], true),
+            (&["//app:__subpackages__"], true),
+            (&["//teams:core"], true),
+            (&["//visibility:private", "//app:__pkg__"], true),
+            (&[], true),
+        ];
+        for (visibility, public) in cases {
+            let deps_graph = graph(&[rule_with_attributes("//app/impl:lib", &[], &[], visibility)]);
+            assert_eq!(
+                deps_graph.is_public("//app/impl:lib"),
+                public,
+                "{:?}",
+                visibility
+            );
+        }
+        assert!(graph(&[]).is_public("//app/impl:lib"));
+    }
+
+    #[test]
+    fn patterns_match_labels_like_bazel() {
+        let cases = [
+            ("//foo:lib", "//...", true),
+            ("//foo:lib", "...", true),
+            ("@maven//:guava", "//...", false),
+            ("//foo:lib", "//foo/...", true),
+            ("//foo/bar:lib", "//foo/...", true),
+            ("//foo/bar:lib", "//foo/...:all", true),
+            ("//foobar:lib", "//foo/...", false),
+            ("//foo:lib", "//foo:all", true),
+            ("//foo:lib", "//foo:*", true),
+            ("//foo:lib", "//foo:all-targets", true),
+            ("//foo/bar:lib", "//foo:all", false),
+            ("//foo:lib", "//foo:lib", true),
+            ("//foo:lib", "//foo:bin", false),
+            ("//foo:lib", "//foo", false),
+        ];
+        for (label, pattern, matches) in cases {
+            assert_eq!(
+                label_matches_pattern(label, pattern),
+                matches,
+                "{} against {}",
+                label,
+                pattern
+            );
+        }
+    }
+
+    #[test]
+    fn deps_and_rdeps_of_labels_and_patterns() {
+        let deps_graph = graph(&[
+            rule("//app:bin", "java_binary", &["//app:lib"]),
+            rule("//app:lib", "java_library", &["//lib/a:lib", "//lib/b:lib"]),
+            rule(
+                "//lib/a:lib",
+                "java_library",
+                &["//lib/a:A.java", "//core:lib"],
+            ),
+            rule("//lib/b:lib", "java_library", &["//core:lib"]),
+            rule("//core:lib", "java_library", &["//core:Core.java"]),
+            source("//lib/a:A.java"),
+            source("//core:Core.java"),
+        ]);
+        let cases: [(&str, bool, &[&str], &[&str]); 8] = [
+            (
+                "//app:lib",
+                false,
+                &["//lib/a:lib", "//lib/b:lib"],
+                &["//app:bin"],
+            ),
+            (
+                "//app:lib",
+                true,
+                &["//core:lib", "//lib/a:lib", "//lib/b:lib"],
+                &["//app:bin"],
+            ),
+            ("//core:lib", false, &[], &["//lib/a:lib", "//lib/b:lib"]),
+            (
+                "//core:lib",
+                true,
+                &[],
+                &["//app:bin", "//app:lib", "//lib/a:lib", "//lib/b:lib"],
+            ),
+            // patterns union the results of the rules they match.
+            ("//lib/...", false, &["//core:lib"], &["//app:lib"]),
+            (
+                "//lib/...",
+                true,
+                &["//core:lib"],
+                &["//app:bin", "//app:lib"],
+            ),
+            (
+                "//app:all",
+                false,
+                &["//app:lib", "//lib/a:lib", "//lib/b:lib"],
+                &["//app:bin"],
+            ),
+            ("//nothing/...", true, &[], &[]),
+        ];
+        for (pattern, transitive, deps, rdeps) in cases {
+            assert_eq!(
+                deps_graph.deps(pattern, transitive).unwrap(),
+                deps,
+                "deps {} {}",
+                pattern,
+                transitive
+            );
+            assert_eq!(
+                deps_graph.rdeps(pattern, transitive).unwrap(),
+                rdeps,
+                "rdeps {} {}",
+                pattern,
+                transitive
+            );
+        }
+
+        for pattern in ["lib/...", ":all", "lib:*"] {
+            for transitive in [false, true] {
+                for result in [
+                    deps_graph.deps(pattern, transitive),
+                    deps_graph.rdeps(pattern, transitive),
+                ] {
+                    assert!(
+                        matches!(result, Err(DepsawError::InvalidInput(_))),
+                        "{}",
+                        pattern
+                    );
+                }
+            }
+        }
+        assert!(matches!(
+            deps_graph.deps("//missing:lib", false),
+            Err(DepsawError::TargetNotFound(_))
+        ));
+        assert!(matches!(
+            deps_graph.rdeps("//missing:lib", true),
+            Err(DepsawError::TargetNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn owners_are_found_by_path_or_label() {
+        let deps_graph = graph(&[
+            rule(
+                "//a:lib",
+                "java_library",
+                &["//a:A.java", "//shared:S.java"],
+            ),
+            rule("//b:lib", "java_library", &["//shared:S.java"]),
+            source("//a:A.java"),
+            source("//shared:S.java"),
+        ]);
+        assert_eq!(
+            deps_graph.owners("shared/S.java"),
+            vec!["//a:lib", "//b:lib"]
+        );
+        assert_eq!(deps_graph.owners("//a:A.java"), vec!["//a:lib"]);
+        assert!(deps_graph.owners("a/Missing.java").is_empty());
+    }
+
+    #[test]
+    fn relative_patterns_are_rejected() {
+        let graph = BazelDependencyGraph::from_string(EXAMPLE, ParseOptions::default()).unwrap();
+        for pattern in ["src/...", "src:all", ":*"] {
+            let err = graph.expand_target_pattern(pattern).unwrap_err();
+            assert!(matches!(err, DepsawError::InvalidInput(_)), "{}", pattern);
+        }
+        let labels = graph
+            .expand_target_pattern("//src/main/java/com/example/dishes/...")
+            .unwrap();
+        assert_eq!(
+            labels,
+            vec![
+                "//src/main/java/com/example/dishes:macAndCheese",
+                "//src/main/java/com/example/dishes:pizza"
+            ]
+        );
+        assert_eq!(
+            graph.expand_target_pattern("...").unwrap().len(),
+            graph.expand_target_pattern("//...").unwrap().len()
+        );
+        assert_eq!(
+            graph.expand_target_pattern("//foo:lib").unwrap(),
+            vec!["//foo:lib"]
+        );
+    }
+
+    #[test]
+    fn long_queries_are_passed_in_a_file() {
+        let targets = vec!["//a:bin".to_string(), "//b:bin".to_string()];
+        let invocation = query_invocation("bazel", &targets, argv::MAX_ARGV_BYTES).unwrap();
+        assert_eq!(
+            invocation.args,
+            vec![
+                "query",
+                "deps(//a:bin union //b:bin)",
+                "--output",
+                "streamed_jsonproto"
+            ]
+        );
+        assert!(invocation.input_file.is_none());
+
+        let invocation = query_invocation("bazel", &targets, 16).unwrap();
+        let query_file = invocation.input_file.as_ref().unwrap().path();
+        assert_eq!(
+            invocation.args,
+            vec![
+                "query".to_string(),
+                format!("--query_file={}", query_file.display()),
+                "--output".to_string(),
+                "streamed_jsonproto".to_string(),
+            ]
+        );
+        assert_eq!(
+            std::fs::read_to_string(query_file).unwrap(),
+            "deps(//a:bin union //b:bin)"
+        );
+    }
+
+    fn entries(lines: &[String]) -> HashMap<String, DependencyEntry> {
+        lines
+            .iter()
+            .map(|line| {
+                let entry: DependencyEntry = serde_json::from_str(line).unwrap();
+                let name = match &entry {
+                    DependencyEntry::Rule { rule } => rule.name.clone(),
+                    DependencyEntry::SourceFile { source_file } => source_file.name.clone(),
+                    DependencyEntry::GeneratedFile { generated_file } => {
+                        generated_file.name.clone()
+                    }
+                    _ => unreachable!("only rules and files are used"),
+                };
+                (name, entry)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn generated_files_resolve_through_chains() {
+        let targets_by_label = entries(&[
+            rule("//x:gen", "genrule", &[]),
+            generated("//x:b.out", "//x:gen"),
+            // a generated file reported as generated by another.
+            generated("//x:a.out", "//x:b.out"),
+            source("//x:in.txt"),
+        ]);
+        for label in ["//x:a.out", "//x:b.out"] {
+            assert_eq!(
+                resolve_generating_rule(label, &targets_by_label),
+                Some("//x:gen".to_string())
+            );
+        }
+        assert_eq!(
+            resolve_generating_rule("//x:in.txt", &targets_by_label),
+            None
+        );
+        assert_eq!(
+            resolve_generating_rule("//x:missing.out", &targets_by_label),
+            None
+        );
+    }
+
+    #[test]
+    fn generated_file_cycles_resolve_to_nothing() {
+        let targets_by_label = entries(&[
+            generated("//x:self.out", "//x:self.out"),
+            generated("//x:a.out", "//x:b.out"),
+            generated("//x:b.out", "//x:a.out"),
+        ]);
+        for label in ["//x:self.out", "//x:a.out", "//x:b.out"] {
+            assert_eq!(resolve_generating_rule(label, &targets_by_label), None);
+        }
+
+        let deps_graph = graph(&[
+            rule("//app:lib", "java_library", &["//x:a.out", "//x:gen.out"]),
+            generated("//x:a.out", "//x:b.out"),
+            generated("//x:b.out", "//x:a.out"),
+            // a rule consuming its own output.
+            rule("//x:gen", "genrule", &["//x:gen.out", "//x:in.txt"]),
+            generated("//x:gen.out", "//x:gen"),
+            source("//x:in.txt"),
+        ]);
+        assert_eq!(
+            deps_graph.rules_by_label["//app:lib"].dep_targets,
+            vec!["//x:gen"]
+        );
+        let generator = &deps_graph.rules_by_label["//x:gen"];
+        assert!(generator.dep_targets.is_empty());
+        assert_eq!(generator.source_files, vec!["//x:in.txt"]);
+    }
+
+    #[test]
+    fn proto_codegen_chain_is_wired_to_generating_rules() {
+        let deps_graph = graph(&[
+            rule(
+                "//app:lib",
+                "java_library",
+                &[
+                    "//codegen:Wrapper.java",
+                    "//proto:Api.java",
+                    "//app:Main.java",
+                ],
+            ),
+            source("//app:Main.java"),
+            rule(
+                "//codegen:wrapper_gen",
+                "genrule",
+                &["//proto:Api.java", "//codegen:template.txt"],
+            ),
+            generated("//codegen:Wrapper.java", "//codegen:wrapper_gen"),
+            source("//codegen:template.txt"),
+            rule("//proto:api_java_gen", "genrule", &["//proto:api_proto"]),
+            generated("//proto:Api.java", "//proto:api_java_gen"),
+            rule("//proto:api_proto", "proto_library", &["//proto:api.proto"]),
+            source("//proto:api.proto"),
+        ]);
+        let entry = |label: &str| &deps_graph.rules_by_label[label];
+        assert_eq!(
+            entry("//app:lib").dep_targets,
+            vec!["//codegen:wrapper_gen", "//proto:api_java_gen"]
+        );
+        assert_eq!(entry("//app:lib").source_files, vec!["//app:Main.java"]);
+        assert_eq!(
+            entry("//codegen:wrapper_gen").dep_targets,
+            vec!["//proto:api_java_gen"]
+        );
+        assert_eq!(
+            entry("//codegen:wrapper_gen").source_files,
+            vec!["//codegen:template.txt"]
+        );
+        assert_eq!(
+            entry("//proto:api_java_gen").dep_targets,
+            vec!["//proto:api_proto"]
+        );
+        assert_eq!(
+            entry("//proto:api_proto").source_files,
+            vec!["//proto:api.proto"]
+        );
+        // commits to the proto flow up to the library using its generated code.
+        assert!(deps_graph
+            .transitive_deps(&["//app:lib".to_string()])
+            .contains("//proto:api_proto"));
+    }
 }
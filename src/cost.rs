@@ -0,0 +1,148 @@
+//! Reading the build cost of targets, to weight trigger scores by.
+//!
+//! Cost files map a label to a numeric cost, such as seconds of build time
+//! exported from remote execution logs, and can be either:
+//!
+//! - a JSON object of label to cost.
+//! - CSV rows of `label,cost`, optionally starting with a header row.
+//!
+//! The format is detected from the first byte of the content, so cost files
+//! can be read from stdin (`-`) or gzipped like other inputs.
+use super::error::{DepsawError, Result};
+use super::input;
+use std::collections::HashMap;
+use std::io::{BufRead, Read};
+use tracing::info;
+
+pub fn read_costs(path: &str) -> Result<HashMap<String, f64>> {
+    let mut reader = input::open(path)?;
+    let is_json = reader
+        .fill_buf()?
+        .iter()
+        .find(|b| !b.is_ascii_whitespace())
+        .is_some_and(|b| *b == b'{');
+    let costs = if is_json {
+        let mut content = String::new();
+        reader.read_to_string(&mut content)?;
+        serde_json::from_str::<HashMap<String, f64>>(&content)?
+    } else {
+        read_csv_costs(reader)?
+    };
+    for (label, cost) in costs.iter() {
+        // costs are compared and multiplied, so they must be real numbers.
+        if !cost.is_finite() || *cost < 0.0 {
+            return Err(DepsawError::ParseError(format!(
+                "invalid cost {} for {} in {}, expected a non-negative number",
+                cost, label, path
+            )));
+        }
+    }
+    info!("read costs of {} targets from {}", costs.len(), path);
+    Ok(costs)
+}
+
+fn read_csv_costs(reader: impl Read) -> Result<HashMap<String, f64>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        // short rows are reported below, with the line they're on.
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let mut costs = HashMap::new();
+    for (i, record) in rdr.records().enumerate() {
+        let record = record?;
+        let (Some(label), Some(cost)) = (record.get(0), record.get(1)) else {
+            return Err(DepsawError::ParseError(format!(
+                "line {} of the cost file: expected label,cost",
+                i + 1
+            )));
+        };
+        match cost.parse::<f64>() {
+            Ok(cost) => {
+                costs.insert(label.to_string(), cost);
+            }
+            // a header row.
+            Err(_) if i == 0 => continue,
+            Err(e) => {
+                return Err(DepsawError::ParseError(format!(
+                    "line {} of the cost file: invalid cost {}: {}",
+                    i + 1,
+                    cost,
+                    e
+                )));
+            }
+        }
+    }
+    Ok(costs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn costs_of(dir: &TempDir, name: &str, content: &str) -> Result<HashMap<String, f64>> {
+        dir.write(name, content);
+        read_costs(&dir.join(name))
+    }
+
+    fn sorted(costs: &HashMap<String, f64>) -> Vec<(&str, f64)> {
+        let mut costs: Vec<_> = costs.iter().map(|(l, c)| (l.as_str(), *c)).collect();
+        costs.sort_by(|a, b| a.0.cmp(b.0));
+        costs
+    }
+
+    #[test]
+    fn format_is_detected_from_the_content() {
+        let dir = TempDir::new("costs");
+        let expected = vec![("//a:lib", 1.5), ("//b:lib", 30.0)];
+        let json = costs_of(&dir, "costs.txt", "\n  {\"//a:lib\": 1.5, \"//b:lib\": 30}").unwrap();
+        assert_eq!(sorted(&json), expected);
+        let csv = costs_of(&dir, "costs.json", "//a:lib,1.5\n //b:lib , 30\n").unwrap();
+        assert_eq!(sorted(&csv), expected);
+    }
+
+    #[test]
+    fn csv_header_row_is_skipped() {
+        let csv = "label,seconds\n//a:lib,1.5\n";
+        let costs = read_csv_costs(csv.as_bytes()).unwrap();
+        assert_eq!(sorted(&costs), vec![("//a:lib", 1.5)]);
+        // only the first row can be a header.
+        let err = read_csv_costs("//a:lib,1.5\nlabel,seconds\n".as_bytes()).unwrap_err();
+        assert!(matches!(err, DepsawError::ParseError(_)));
+        assert!(err.to_string().contains("line 2"), "{}", err);
+    }
+
+    #[test]
+    fn csv_rows_need_a_label_and_cost() {
+        let err = read_csv_costs("//a:lib,1.5\n//b:lib\n".as_bytes()).unwrap_err();
+        assert!(matches!(err, DepsawError::ParseError(_)));
+        assert!(err
+            .to_string()
+            .contains("line 2 of the cost file: expected label,cost"));
+    }
+
+    #[test]
+    fn negative_and_non_finite_costs_are_rejected() {
+        let dir = TempDir::new("bad-costs");
+        for (name, content) in [
+            ("negative.json", "{\"//a:lib\": -1}"),
+            ("negative.csv", "//a:lib,-1\n"),
+            ("nan.csv", "//a:lib,NaN\n"),
+            ("inf.csv", "//a:lib,inf\n"),
+        ] {
+            let err = costs_of(&dir, name, content).unwrap_err();
+            assert!(matches!(err, DepsawError::ParseError(_)), "{}", name);
+            assert!(
+                err.to_string().contains("invalid cost") && err.to_string().contains("//a:lib"),
+                "{}: {}",
+                name,
+                err
+            );
+        }
+        assert_eq!(
+            sorted(&costs_of(&dir, "zero.csv", "//a:lib,0\n").unwrap()),
+            vec![("//a:lib", 0.0)]
+        );
+    }
+}
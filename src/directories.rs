@@ -0,0 +1,110 @@
+//! Synthesize a dependency graph from directories, for repositories that
+//! don't use bazel.
+//!
+//! Each directory up to a given depth becomes a pseudo-target labeled like a
+//! bazel package (`//foo/bar`, or `//` for the repository root), whose source
+//! files are the files in it. Files nested deeper than the depth belong to
+//! their ancestor at that depth. Directories don't depend on each other:
+//! instead every directory owns all of the files under it, so a parent
+//! aggregates its children and each directory is scored on the distinct
+//! commits touching anything under it.
+use super::bazel::{BazelDependencyGraph, Entry};
+use super::git::GitRepo;
+use std::collections::BTreeMap;
+
+/// the rule class given to each directory pseudo-target.
+const DIRECTORY_RULE_CLASS: &str = "directory";
+
+pub fn graph_from_directories(repo: &GitRepo, depth: usize) -> BazelDependencyGraph {
+    let mut rules_by_label: BTreeMap<String, Entry> = BTreeMap::new();
+    rules_by_label.insert(directory_label(&[]), new_entry());
+    for path in repo.files.keys() {
+        let components: Vec<&str> = path.split('/').collect();
+        let (dirs, _) = components.split_at(components.len() - 1);
+        let owner_depth = dirs.len().min(depth);
+
+        // the file belongs to every directory down to the owning one.
+        for i in 0..=owner_depth {
+            let label = directory_label(&dirs[..i]);
+            let name = components[i..].join("/");
+            rules_by_label
+                .entry(label.clone())
+                .or_insert_with(new_entry)
+                .source_files
+                .push(format!("{}:{}", label, name));
+        }
+    }
+    for entry in rules_by_label.values_mut() {
+        entry.source_files.sort();
+    }
+    BazelDependencyGraph {
+        rules_by_label,
+        external_references_by_repo: BTreeMap::new(),
+    }
+}
+
+fn directory_label(dirs: &[&str]) -> String {
+    format!("//{}", dirs.join("/"))
+}
+
+fn new_entry() -> Entry {
+    Entry {
+        rule_class: DIRECTORY_RULE_CLASS.to_string(),
+        dep_targets: vec![],
+        source_files: vec![],
+        exports: vec![],
+        visibility: vec![],
+        external_dep_count: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::calculate_trigger_scores;
+    use crate::testing::repo;
+
+    #[test]
+    fn directories_own_the_files_under_them() {
+        let git_repo = repo(&[
+            ("README.md", &["c1"]),
+            ("a/A.java", &["c2"]),
+            ("a/b/B.java", &["c2", "c3"]),
+            ("a/b/c/C.java", &["c4"]),
+        ]);
+        let deps_graph = graph_from_directories(&git_repo, 2);
+        let labels: Vec<_> = deps_graph
+            .rules_by_label
+            .keys()
+            .map(|l| l.as_str())
+            .collect();
+        assert_eq!(labels, vec!["//", "//a", "//a/b"]);
+        assert_eq!(
+            deps_graph.rules_by_label["//a/b"].source_files,
+            vec!["//a/b:B.java", "//a/b:c/C.java"]
+        );
+        assert!(deps_graph
+            .rules_by_label
+            .values()
+            .all(|e| e.dep_targets.is_empty()));
+    }
+
+    #[test]
+    fn directories_are_scored_on_their_distinct_commits() {
+        let git_repo = repo(&[
+            ("README.md", &["c1"]),
+            ("a/A.java", &["c2"]),
+            ("a/b/B.java", &["c2", "c3"]),
+            ("a/b/c/C.java", &["c4"]),
+        ]);
+        let deps_graph = graph_from_directories(&git_repo, 2);
+        let scores_by_target =
+            calculate_trigger_scores(&["//...".to_string()], &git_repo, &deps_graph).unwrap();
+        let mut scores: Vec<_> = scores_by_target
+            .values()
+            .map(|t| (t.name.as_str(), t.rebuilds, t.score))
+            .collect();
+        scores.sort();
+        assert_eq!(scores, vec![("//", 4, 4), ("//a", 3, 3), ("//a/b", 3, 3)]);
+    }
+}
@@ -0,0 +1,139 @@
+//! Rendering the dependency graph as Graphviz DOT, annotated with trigger
+//! scores.
+//!
+//! Nodes are filled with a five bucket heat map of their score relative to
+//! the highest scoring node, so hot spots stand out when rendered with
+//! `dot -Tsvg`.
+use super::algorithms::trigger_scores::ResolvedTarget;
+use super::bazel::BazelDependencyGraph;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+/// fill colors from coldest to hottest.
+const HEAT_COLORS: [&str; 5] = ["#ffffb2", "#fecc5c", "#fd8d3c", "#f03b20", "#bd0026"];
+
+/// render the scored targets and the dependencies between them. With
+/// `max_nodes`, only the highest scoring targets are kept, along with the
+/// edges between them.
+pub fn render(
+    scores_by_target: &HashMap<String, ResolvedTarget>,
+    deps_graph: &BazelDependencyGraph,
+    max_nodes: Option<usize>,
+) -> String {
+    let mut targets: Vec<_> = scores_by_target.values().collect();
+    targets.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.name.cmp(&b.name)));
+    if let Some(max_nodes) = max_nodes {
+        targets.truncate(max_nodes);
+    }
+    let max_score = targets.iter().map(|t| t.score).max().unwrap_or(0);
+    let included: HashSet<&str> = targets.iter().map(|t| t.name.as_str()).collect();
+
+    let mut dot = String::new();
+    dot.push_str("digraph depsaw {\n");
+    dot.push_str("  node [shape=box, style=filled];\n");
+    for target in targets.iter() {
+        // writing to a String can't fail.
+        let _ = writeln!(
+            dot,
+            "  {} [label={}, fillcolor=\"{}\"];",
+            quote(&target.name),
+            quote(&format!(
+                "{}\nrebuilds: {}\nscore: {}",
+                target.name, target.rebuilds, target.score
+            )),
+            heat_color(target.score, max_score)
+        );
+    }
+    for target in targets.iter() {
+        let Some(entry) = deps_graph.rules_by_label.get(&target.name) else {
+            continue;
+        };
+        for dep in entry.dep_targets.iter() {
+            if included.contains(dep.as_str()) {
+                let _ = writeln!(dot, "  {} -> {};", quote(&target.name), quote(dep));
+            }
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+fn heat_color(score: usize, max_score: usize) -> &'static str {
+    if max_score == 0 {
+        return HEAT_COLORS[0];
+    }
+    let bucket = score * HEAT_COLORS.len() / max_score;
+    HEAT_COLORS[bucket.min(HEAT_COLORS.len() - 1)]
+}
+
+/// a DOT quoted string.
+fn quote(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::calculate_trigger_scores;
+    use crate::testing::{graph, repo, rule, source};
+
+    /// the node and edge statements of a rendered graph.
+    fn statements(dot: &str) -> (Vec<&str>, Vec<&str>) {
+        let body: Vec<&str> = dot
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| line.ends_with(';') && !line.starts_with("node "))
+            .collect();
+        body.iter().partition(|line| !line.contains(" -> "))
+    }
+
+    fn render_example(max_nodes: Option<usize>) -> String {
+        let deps_graph = graph(&[
+            rule("//app:bin", "java_binary", &["//a:lib", "//b:lib"]),
+            rule("//a:lib", "java_library", &["//c:lib", "//a:A.java"]),
+            rule("//b:lib", "java_library", &["//c:lib"]),
+            rule("//c:lib", "java_library", &["//c:C.java"]),
+            source("//a:A.java"),
+            source("//c:C.java"),
+        ]);
+        let git_repo = repo(&[("a/A.java", &["c1"]), ("c/C.java", &["c2", "c3"])]);
+        let scores_by_target =
+            calculate_trigger_scores(&["//app:bin".to_string()], &git_repo, &deps_graph).unwrap();
+        render(&scores_by_target, &deps_graph, max_nodes)
+    }
+
+    #[test]
+    fn renders_every_target_and_dependency() {
+        let dot = render_example(None);
+        assert!(dot.starts_with("digraph depsaw {\n"));
+        assert!(dot.ends_with("}\n"));
+        let (nodes, edges) = statements(&dot);
+        assert_eq!(nodes.len(), 4);
+        assert_eq!(edges.len(), 4);
+        assert!(edges.contains(&"\"//a:lib\" -> \"//c:lib\";"));
+        // //c:lib is rebuilt by 2 commits and has 3 dependents.
+        assert!(dot.contains(
+            "\"//c:lib\" [label=\"//c:lib\\nrebuilds: 2\\nscore: 8\", fillcolor=\"#bd0026\"];"
+        ));
+    }
+
+    #[test]
+    fn max_nodes_keeps_the_edges_between_the_top_targets() {
+        let dot = render_example(Some(2));
+        let (nodes, edges) = statements(&dot);
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(edges, vec!["\"//a:lib\" -> \"//c:lib\";"]);
+    }
+
+    #[test]
+    fn labels_are_quoted() {
+        assert_eq!(quote("//a:\"b\"\\c"), "\"//a:\\\"b\\\"\\\\c\"");
+        assert_eq!(heat_color(0, 0), HEAT_COLORS[0]);
+        assert_eq!(heat_color(5, 10), HEAT_COLORS[2]);
+        assert_eq!(heat_color(10, 10), HEAT_COLORS[4]);
+    }
+}
@@ -0,0 +1,142 @@
+//! The error type shared across depsaw.
+//!
+//! Each variant maps to a distinct process exit code, so that scripts can
+//! tell failure categories apart without parsing messages.
+use std::fmt;
+
+#[derive(Debug)]
+pub enum DepsawError {
+    /// a git command failed, or could not be run at all.
+    GitCommandFailed(String),
+    /// a bazel query failed, or could not be run at all.
+    BazelQueryFailed(String),
+    /// the label was not found in the dependency graph.
+    TargetNotFound(String),
+    IoError(std::io::Error),
+    /// input such as bazel query output or git history could not be parsed.
+    ParseError(String),
+    /// results or analysis files could not be serialized or deserialized.
+    SerializationError(String),
+    /// the command line arguments are invalid or inconsistent.
+    InvalidInput(String),
+    /// the latest release could not be fetched.
+    UpdateCheckFailed(String),
+    /// two runs of the same analysis produced different results.
+    DeterminismCheckFailed(String),
+    /// another external command, such as buildozer or bazel test, could not
+    /// be run or failed.
+    CommandFailed(String),
+}
+
+pub type Result<T> = std::result::Result<T, DepsawError>;
+
+impl DepsawError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            // 2 matches clap's exit code for usage errors.
+            DepsawError::InvalidInput(_) => 2,
+            DepsawError::GitCommandFailed(_) => 3,
+            DepsawError::BazelQueryFailed(_) => 4,
+            DepsawError::TargetNotFound(_) => 5,
+            DepsawError::IoError(_) => 6,
+            DepsawError::ParseError(_) => 7,
+            DepsawError::SerializationError(_) => 8,
+            DepsawError::UpdateCheckFailed(_) => 9,
+            DepsawError::DeterminismCheckFailed(_) => 10,
+            DepsawError::CommandFailed(_) => 11,
+        }
+    }
+}
+
+impl fmt::Display for DepsawError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DepsawError::GitCommandFailed(msg) => write!(f, "git command failed: {}", msg),
+            DepsawError::BazelQueryFailed(msg) => write!(f, "bazel query failed: {}", msg),
+            DepsawError::TargetNotFound(target) => {
+                write!(f, "target {} not found in dependency graph", target)
+            }
+            DepsawError::IoError(e) => write!(f, "io error: {}", e),
+            DepsawError::ParseError(msg) => write!(f, "parse error: {}", msg),
+            DepsawError::SerializationError(msg) => write!(f, "serialization error: {}", msg),
+            DepsawError::InvalidInput(msg) => write!(f, "{}", msg),
+            DepsawError::UpdateCheckFailed(msg) => write!(f, "update check failed: {}", msg),
+            DepsawError::DeterminismCheckFailed(msg) => {
+                write!(f, "determinism check failed: {}", msg)
+            }
+            DepsawError::CommandFailed(msg) => write!(f, "command failed: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for DepsawError {}
+
+impl From<std::io::Error> for DepsawError {
+    fn from(e: std::io::Error) -> Self {
+        DepsawError::IoError(e)
+    }
+}
+
+impl From<std::string::FromUtf8Error> for DepsawError {
+    fn from(e: std::string::FromUtf8Error) -> Self {
+        DepsawError::ParseError(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for DepsawError {
+    fn from(e: serde_json::Error) -> Self {
+        DepsawError::SerializationError(e.to_string())
+    }
+}
+
+impl From<serde_yaml::Error> for DepsawError {
+    fn from(e: serde_yaml::Error) -> Self {
+        DepsawError::SerializationError(e.to_string())
+    }
+}
+
+impl From<csv::Error> for DepsawError {
+    fn from(e: csv::Error) -> Self {
+        DepsawError::SerializationError(e.to_string())
+    }
+}
+
+impl From<rkyv::rancor::Error> for DepsawError {
+    fn from(e: rkyv::rancor::Error) -> Self {
+        DepsawError::SerializationError(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn exit_codes_are_distinct() {
+        let errors = [
+            DepsawError::GitCommandFailed(String::new()),
+            DepsawError::BazelQueryFailed(String::new()),
+            DepsawError::TargetNotFound(String::new()),
+            DepsawError::IoError(std::io::Error::other("")),
+            DepsawError::ParseError(String::new()),
+            DepsawError::SerializationError(String::new()),
+            DepsawError::InvalidInput(String::new()),
+            DepsawError::UpdateCheckFailed(String::new()),
+            DepsawError::DeterminismCheckFailed(String::new()),
+            DepsawError::CommandFailed(String::new()),
+        ];
+        let codes: HashSet<i32> = errors.iter().map(|e| e.exit_code()).collect();
+        assert_eq!(codes.len(), errors.len());
+        assert!(!codes.contains(&0) && !codes.contains(&1));
+    }
+
+    #[test]
+    fn messages_are_single_line() {
+        let e = DepsawError::TargetNotFound("//foo:bar".to_string());
+        assert_eq!(
+            e.to_string(),
+            "target //foo:bar not found in dependency graph"
+        );
+    }
+}
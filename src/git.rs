@@ -1,92 +1,1351 @@
+use super::error::{DepsawError, Result};
+use super::{analysis_file, argv, metrics};
 use rkyv::{Archive, Deserialize, Serialize};
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::error::Error;
-use tracing::{debug, info};
+use std::io::Write;
+use std::process::Stdio;
+use tracing::{debug, info, warn};
 
 const DEPSAW_COMMIT_PREFIX: &str = "depsaw-commit:";
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+/// the convenience symlinks bazel creates in the workspace root, besides
+/// `bazel-<workspace name>`, named after the name declared in MODULE.bazel
+/// or WORKSPACE.
+const BAZEL_SYMLINKS: [&str; 3] = ["bazel-out", "bazel-bin", "bazel-testlogs"];
+
+/// Options controlling which commits and files are read from git history.
+#[derive(Debug, Clone, PartialEq, Archive, Serialize, Deserialize)]
+pub struct HistoryOptions {
+    /// only consider commits since this date, in git's `--since` format.
+    pub since: Option<String>,
+    /// merge the history of renamed files into their new path.
+    pub detect_renames: bool,
+    /// only record files under these paths. Empty means all files.
+    pub path_prefixes: Vec<String>,
+    /// globs of paths to leave out, in addition to bazel's convenience
+    /// symlinks and the directories listed in `.bazelignore`.
+    pub exclude_paths: Vec<String>,
+}
+
+impl Default for HistoryOptions {
+    fn default() -> Self {
+        HistoryOptions {
+            since: None,
+            detect_renames: true,
+            path_prefixes: vec![],
+            exclude_paths: vec![],
+        }
+    }
+}
 
 #[derive(Debug, Archive, Serialize, Deserialize)]
 pub struct GitRepo {
     pub files: HashMap<String, GitFile>,
+    /// the newest commit included in the analysis, used as the starting
+    /// point for incremental updates.
+    pub last_commit: Option<String>,
+    /// the options the history was read with, which an update must be
+    /// given too. None if unknown, in which case updates start over.
+    pub options: Option<HistoryOptions>,
+    /// the committer timestamp of each recorded commit, to expire the
+    /// commits that fall out of a relative `since` window on update.
+    pub commit_times: HashMap<String, i64>,
+    /// the period of history the analysis covers. None if the repository
+    /// had no commits.
+    pub window: Option<Window>,
+}
+
+/// A period of history, as unix timestamps.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Archive,
+    Serialize,
+    Deserialize,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+pub struct Window {
+    /// the `since` date, or the oldest recorded commit without one.
+    pub start: i64,
+    /// when the history was read.
+    pub end: i64,
+}
+
+impl Window {
+    pub fn days(&self) -> f64 {
+        (self.end - self.start) as f64 / SECONDS_PER_DAY as f64
+    }
+
+    /// the day of the week the window ends on, in UTC, from 0 for Monday.
+    pub fn end_weekday(&self) -> i64 {
+        // the epoch was a Thursday.
+        (self.end.div_euclid(SECONDS_PER_DAY) + 3).rem_euclid(7)
+    }
 }
 
 impl GitRepo {
-    pub fn from_path(path: &str, since: Option<String>) -> Result<GitRepo, Box<dyn Error>> {
+    pub fn from_path(path: &str, options: &HistoryOptions) -> Result<GitRepo> {
         info!("running git repo analysis in {}", path);
-        let files = get_file_commit_history(path, since)?;
-        Ok(GitRepo { files })
+        let mut repo = GitRepo {
+            files: HashMap::new(),
+            last_commit: None,
+            options: Some(options.clone()),
+            commit_times: HashMap::new(),
+            window: None,
+        };
+        let Some(head) = resolve_head(path)? else {
+            // a new repository has no history yet, which is valid.
+            info!("{} does not have any commits yet", path);
+            return Ok(repo);
+        };
+        let log = get_file_commit_history(path, options, &head)?;
+        repo.files = log.files;
+        repo.commit_times = log.commit_times;
+        repo.last_commit = Some(head);
+        let since = match &options.since {
+            Some(since) => Some(since_timestamp(path, since)?),
+            None => None,
+        };
+        repo.apply_options(path, options, since)
     }
 
-    pub fn from_file(path: &str) -> Result<GitRepo, Box<dyn Error>> {
+    pub fn from_file(path: &str) -> Result<GitRepo> {
         info!("reading git repo analysis from {}", path);
-        let content = std::fs::read(path).unwrap();
-        let repo: GitRepo = rkyv::from_bytes::<GitRepo, rkyv::rancor::Error>(&content)?;
+        let content = analysis_file::read_archive(path, analysis_file::Kind::GitRepo)?;
+        rkyv::from_bytes::<GitRepo, rkyv::rancor::Error>(&content)
+            .map_err(|e| analysis_file::corrupted(path, e))
+    }
+
+    pub fn to_file(&self, path: &str) -> Result<()> {
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(self)?;
+        analysis_file::write_archive(path, analysis_file::Kind::GitRepo, &bytes)
+    }
+
+    /// add the commits made since the last analysis, and expire the ones
+    /// before the `since` date. The result is the same as re-running the
+    /// analysis from scratch with the same options, which it falls back to
+    /// when the options differ from the recorded ones or the last commit is
+    /// no longer in HEAD's history, e.g. after a rebase.
+    pub fn update(mut self, path: &str, options: &HistoryOptions) -> Result<GitRepo> {
+        if self.options.as_ref() != Some(options) {
+            warn!("git analysis was recorded with other options, rebuilding from scratch");
+            return GitRepo::from_path(path, options);
+        }
+        let Some(last_commit) = self.last_commit.clone() else {
+            info!("git analysis does not record its last commit, rebuilding from scratch");
+            return GitRepo::from_path(path, options);
+        };
+        let Some(head) = resolve_head(path)? else {
+            warn!(
+                "{} no longer has any commits, rebuilding from scratch",
+                path
+            );
+            return GitRepo::from_path(path, options);
+        };
+        if head == last_commit {
+            info!("git analysis is already up to date at {}", head);
+        } else if !is_ancestor(path, &last_commit, &head)? {
+            warn!(
+                "{} is no longer an ancestor of HEAD, rebuilding from scratch",
+                last_commit
+            );
+            return GitRepo::from_path(path, options);
+        } else {
+            info!(
+                "updating git repo analysis in {} from {}",
+                path, last_commit
+            );
+            let range = format!("{}..{}", last_commit, head);
+            let log = get_file_commit_history(path, options, &range)?;
+            // history recorded under a path that was later renamed moves to
+            // the newest path, as it would in a full analysis.
+            for (old, new) in log.renamed_to {
+                if let Some(file) = self.files.remove(&old) {
+                    self.files
+                        .entry(new)
+                        .or_default()
+                        .commit_history
+                        .extend(file.commit_history);
+                }
+            }
+            for (path, file) in log.files {
+                self.files
+                    .entry(path)
+                    .or_default()
+                    .commit_history
+                    .extend(file.commit_history);
+            }
+            self.commit_times.extend(log.commit_times);
+            self.last_commit = Some(head);
+        }
+        let since = match &options.since {
+            Some(since) => Some(since_timestamp(path, since)?),
+            None => None,
+        };
+        if let Some(since) = since {
+            self.expire_before(since);
+        }
+        self.apply_options(path, options, since)
+    }
+
+    /// leave out the excluded paths and the paths outside of the prefixes,
+    /// as git's own filtering of the history doesn't cover them, and record
+    /// the window of history read, which starts at `since` if given.
+    fn apply_options(
+        mut self,
+        path: &str,
+        options: &HistoryOptions,
+        since: Option<i64>,
+    ) -> Result<GitRepo> {
+        self.remove_excluded(&PathExclusions::load(path, &options.exclude_paths)?);
+        let mut repo = if options.path_prefixes.is_empty() {
+            self
+        } else {
+            // git's pathspec matching is broader than ours (e.g. globs), so
+            // filter again to keep the result consistent with filter_paths.
+            self.filter_paths(&options.path_prefixes)
+        };
+        repo.forget_unused_commits();
+        let end = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        let start = since
+            .or_else(|| repo.commit_times.values().min().copied())
+            .unwrap_or(end);
+        repo.window = Some(Window { start, end });
         Ok(repo)
     }
+
+    /// drop the commits made before the timestamp.
+    fn expire_before(&mut self, timestamp: i64) {
+        let commit_times = &self.commit_times;
+        for file in self.files.values_mut() {
+            file.commit_history
+                .retain(|commit| commit_times.get(commit).is_none_or(|t| *t >= timestamp));
+        }
+        self.files.retain(|_, file| !file.commit_history.is_empty());
+        self.forget_unused_commits();
+    }
+
+    /// drop the times of commits no longer touching any recorded file.
+    fn forget_unused_commits(&mut self) {
+        let used: HashSet<&String> = self
+            .files
+            .values()
+            .flat_map(|file| file.commit_history.iter())
+            .collect();
+        self.commit_times.retain(|commit, _| used.contains(commit));
+    }
+
+    fn remove_excluded(&mut self, exclusions: &PathExclusions) {
+        let before = self.files.len();
+        self.files.retain(|path, _| !exclusions.excludes(path));
+        if self.files.len() < before {
+            info!(
+                "excluded {} paths from the analysis",
+                before - self.files.len()
+            );
+        }
+    }
+
+    /// true if no file was touched by a commit in the analyzed range.
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// invert the file history into a map of commit hash to the files it
+    /// touched.
+    pub fn files_by_commit(&self) -> HashMap<String, Vec<String>> {
+        let mut files_by_commit: HashMap<String, Vec<String>> = HashMap::new();
+        for (path, file) in self.files.iter() {
+            for commit in file.commit_history.iter() {
+                files_by_commit
+                    .entry(commit.clone())
+                    .or_default()
+                    .push(path.clone());
+            }
+        }
+        files_by_commit
+    }
+
+    /// return a copy of the repo only containing files under the given path
+    /// prefixes. Prefixes match whole path components, so `foo` matches
+    /// `foo/bar.rs` but not `foobar.rs`.
+    ///
+    /// The prefixes are recorded in the options, so that updates read the
+    /// same paths. Filtering an analysis already restricted to other
+    /// prefixes forgets the options, as they can't describe both.
+    pub fn filter_paths(&self, prefixes: &[String]) -> GitRepo {
+        let files: HashMap<String, GitFile> = self
+            .files
+            .iter()
+            .filter(|(path, _)| prefixes.iter().any(|p| path_has_prefix(path, p)))
+            .map(|(path, file)| (path.clone(), file.clone()))
+            .collect();
+        let options = self.options.clone().and_then(|mut options| {
+            if options.path_prefixes.is_empty() {
+                options.path_prefixes = prefixes.to_vec();
+            }
+            (options.path_prefixes == prefixes).then_some(options)
+        });
+        let mut repo = GitRepo {
+            files,
+            last_commit: self.last_commit.clone(),
+            options,
+            commit_times: self.commit_times.clone(),
+            window: self.window,
+        };
+        repo.forget_unused_commits();
+        repo
+    }
 }
 
-#[derive(Debug, Archive, Serialize, Deserialize)]
+fn path_has_prefix(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    if prefix.is_empty() {
+        return true;
+    }
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// paths whose changes never trigger builds: bazel's convenience symlinks,
+/// directories listed in `.bazelignore`, and user provided globs.
+struct PathExclusions {
+    /// excluded directories, relative to the workspace root.
+    directories: Vec<String>,
+    globs: Vec<String>,
+}
+
+impl PathExclusions {
+    fn load(workspace_root: &str, globs: &[String]) -> Result<PathExclusions> {
+        let mut directories: Vec<String> = BAZEL_SYMLINKS.iter().map(|s| s.to_string()).collect();
+        // bazel names the symlink to the execroot after the workspace.
+        if let Some(name) = workspace_name(workspace_root)? {
+            directories.push(format!("bazel-{}", name));
+        }
+        match std::fs::read_to_string(std::path::Path::new(workspace_root).join(".bazelignore")) {
+            Ok(content) => directories.extend(
+                content
+                    .lines()
+                    .map(|line| line.trim().trim_end_matches('/'))
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(|line| line.to_string()),
+            ),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(PathExclusions {
+            directories,
+            // a trailing `/` means the directory, which matches its files
+            // like any glob matching a parent directory.
+            globs: globs
+                .iter()
+                .map(|g| g.trim_end_matches('/').to_string())
+                .collect(),
+        })
+    }
+
+    /// a path is excluded if it is under an excluded directory, or if it or
+    /// any of its parent directories matches a glob.
+    fn excludes(&self, path: &str) -> bool {
+        if self.directories.iter().any(|d| path_has_prefix(path, d)) {
+            return true;
+        }
+        let mut candidate = path;
+        loop {
+            if self
+                .globs
+                .iter()
+                .any(|g| glob_matches(g.as_bytes(), candidate.as_bytes()))
+            {
+                return true;
+            }
+            match candidate.rsplit_once('/') {
+                Some((parent, _)) => candidate = parent,
+                None => return false,
+            }
+        }
+    }
+}
+
+/// the files that can declare the name of the workspace, along with the
+/// function declaring it, in the order bazel prefers them.
+const WORKSPACE_FILES: [(&str, &str); 3] = [
+    ("MODULE.bazel", "module"),
+    ("WORKSPACE.bazel", "workspace"),
+    ("WORKSPACE", "workspace"),
+];
+
+/// the name declared by the workspace's MODULE.bazel or WORKSPACE file, if
+/// any.
+fn workspace_name(workspace_root: &str) -> Result<Option<String>> {
+    for (file, function) in WORKSPACE_FILES {
+        match std::fs::read_to_string(std::path::Path::new(workspace_root).join(file)) {
+            Ok(content) => {
+                if let Some(name) = declared_name(&content, function) {
+                    return Ok(Some(name));
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(None)
+}
+
+/// the `name` argument of the first call to the function, e.g. `my_repo` in
+/// `module(name = "my_repo", version = "1.0")`.
+fn declared_name(content: &str, function: &str) -> Option<String> {
+    let mut rest = content;
+    while let Some(start) = rest.find(function) {
+        let preceded_by_identifier = rest[..start]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '.');
+        let after = &rest[start + function.len()..];
+        rest = after;
+        let Some(call) = after.trim_start().strip_prefix('(') else {
+            continue;
+        };
+        if preceded_by_identifier {
+            continue;
+        }
+        let arguments = call.split(')').next().unwrap_or_default();
+        return arguments.split(',').find_map(|argument| {
+            let (key, value) = argument.split_once('=')?;
+            if key.trim() != "name" {
+                return None;
+            }
+            let value = value.trim();
+            let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+            Some(value[1..].split(quote).next()?.to_string())
+        });
+    }
+    None
+}
+
+/// match a path against a glob, where `*` matches within a path component,
+/// `**` matches across components and `?` matches a single character.
+fn glob_matches(glob: &[u8], path: &[u8]) -> bool {
+    match glob {
+        [] => path.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            // `**/` also matches no directories at all.
+            if let Some(after_slash) = rest.strip_prefix(b"/") {
+                if glob_matches(after_slash, path) {
+                    return true;
+                }
+            }
+            (0..=path.len()).any(|i| glob_matches(rest, &path[i..]))
+        }
+        [b'*', rest @ ..] => {
+            for i in 0..=path.len() {
+                if glob_matches(rest, &path[i..]) {
+                    return true;
+                }
+                if i < path.len() && path[i] == b'/' {
+                    break;
+                }
+            }
+            false
+        }
+        [b'?', rest @ ..] => {
+            matches!(path.first(), Some(c) if *c != b'/') && glob_matches(rest, &path[1..])
+        }
+        [c, rest @ ..] => path.first() == Some(c) && glob_matches(rest, &path[1..]),
+    }
+}
+
+/// How a file in the working tree relates to the repository.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileStatus {
+    Tracked,
+    /// untracked, and not ignored, such as a scratch file.
+    Untracked,
+    /// untracked, and ignored by a `.gitignore` file, `.git/info/exclude` or
+    /// the global excludes file.
+    Ignored,
+}
+
+/// Whether files in the working tree belong to the repository, so that
+/// counting lines of code doesn't depend on untracked scratch files or
+/// ignored paths lying around in it. Only tracked files are relevant.
+///
+/// Line counting is the only analysis reading the working tree. The git
+/// history, and the trigger scores computed from it, only ever see committed
+/// paths, so they don't need to consult this.
+///
+/// The tracked files are listed once, and untracked files are checked
+/// against the ignores in batches with `git check-ignore --stdin`. Results
+/// are cached for the run. Outside of a git repository, every file is
+/// treated as tracked.
+pub struct FileRelevance {
+    repo_path: String,
+    /// None outside of a git repository.
+    tracked: Option<HashSet<String>>,
+    status_by_path: HashMap<String, FileStatus>,
+}
+
+impl FileRelevance {
+    pub fn new(repo_path: &str) -> Result<FileRelevance> {
+        let mut relevance = FileRelevance {
+            repo_path: repo_path.to_string(),
+            tracked: None,
+            status_by_path: HashMap::new(),
+        };
+        let args = vec!["rev-parse".to_string(), "--is-inside-work-tree".to_string()];
+        if !run_git(repo_path, &args)?.status.success() {
+            debug!(
+                "{} is not in a git repository, treating every file as tracked",
+                repo_path
+            );
+            return Ok(relevance);
+        }
+        // paths are listed relative to the repo path, like the paths
+        // checked against them.
+        let args = vec!["ls-files".to_string(), "-z".to_string()];
+        let output = run_git(repo_path, &args)?;
+        if !output.status.success() {
+            return Err(DepsawError::GitCommandFailed(format!(
+                "git ls-files: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        let tracked: HashSet<String> = String::from_utf8(output.stdout)?
+            .split('\0')
+            .filter(|path| !path.is_empty())
+            .map(|path| path.to_string())
+            .collect();
+        debug!("{} files are tracked in {}", tracked.len(), repo_path);
+        relevance.tracked = Some(tracked);
+        Ok(relevance)
+    }
+
+    /// check the paths, relative to the repo path, that aren't cached yet in
+    /// a single batch.
+    pub fn check(&mut self, paths: &[String]) -> Result<()> {
+        let Some(tracked) = &self.tracked else {
+            return Ok(());
+        };
+        let mut untracked = vec![];
+        for path in paths {
+            if self.status_by_path.contains_key(path) {
+                continue;
+            }
+            if tracked.contains(path) {
+                self.status_by_path
+                    .insert(path.clone(), FileStatus::Tracked);
+            } else {
+                untracked.push(path);
+            }
+        }
+        untracked.sort();
+        untracked.dedup();
+        if untracked.is_empty() {
+            return Ok(());
+        }
+        let args: Vec<String> = ["check-ignore", "--stdin", "-z"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let input: String = untracked.iter().map(|path| format!("{}\0", path)).collect();
+        let output = run_git_with_input(&self.repo_path, &args, Some(input))?;
+        // check-ignore exits with 1 when none of the paths are ignored.
+        if !matches!(output.status.code(), Some(0) | Some(1)) {
+            return Err(DepsawError::GitCommandFailed(format!(
+                "git check-ignore: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        let stdout = String::from_utf8(output.stdout)?;
+        let ignored: HashSet<&str> = stdout.split('\0').filter(|p| !p.is_empty()).collect();
+        for path in untracked {
+            let status = if ignored.contains(path.as_str()) {
+                FileStatus::Ignored
+            } else {
+                FileStatus::Untracked
+            };
+            self.status_by_path.insert(path.clone(), status);
+        }
+        Ok(())
+    }
+
+    /// the status of the path, relative to the repo path. Paths that weren't
+    /// checked in a batch yet are checked on their own.
+    pub fn status(&mut self, path: &str) -> Result<FileStatus> {
+        if self.tracked.is_none() {
+            return Ok(FileStatus::Tracked);
+        }
+        if !self.status_by_path.contains_key(path) {
+            self.check(&[path.to_string()])?;
+        }
+        Ok(self.status_by_path[path])
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Archive, Serialize, Deserialize)]
 pub struct GitFile {
     pub commit_history: HashSet<String>,
 }
 
-fn get_file_commit_history(
+/// run git in the repository, returning its output regardless of exit status.
+fn run_git(repo_path: &str, args: &[String]) -> Result<std::process::Output> {
+    run_git_with_input(repo_path, args, None)
+}
+
+/// run git in the repository with the given input on stdin.
+fn run_git_with_input(
     repo_path: &str,
-    since: Option<String>,
-) -> Result<HashMap<String, GitFile>, Box<dyn Error>> {
-    let mut file_commits: HashMap<String, GitFile> = HashMap::new();
+    args: &[String],
+    input: Option<String>,
+) -> Result<std::process::Output> {
+    let prog = "git";
+    let cmd = format!("{} {}", prog, args.join(" "));
+    debug!(repo_path, cmd, "running git");
+    metrics::record_subprocess();
+    let spawn_error = |e: std::io::Error| {
+        DepsawError::GitCommandFailed(format!("unable to run {} in {}: {}", cmd, repo_path, e))
+    };
+    let mut child = std::process::Command::new(prog)
+        .current_dir(repo_path)
+        .args(args)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(spawn_error)?;
+    // write from a separate thread, so git never blocks writing its output
+    // while we block writing its input.
+    let writer = match (input, child.stdin.take()) {
+        (Some(input), Some(mut stdin)) => Some(std::thread::spawn(move || {
+            stdin.write_all(input.as_bytes())
+        })),
+        _ => None,
+    };
+    let output = child.wait_with_output().map_err(spawn_error)?;
+    if let Some(writer) = writer {
+        // git exiting early, e.g. on a bad revision, closes stdin. The exit
+        // status reports that failure better than the broken pipe.
+        if let Ok(Err(e)) = writer.join() {
+            debug!("unable to write input to git: {}", e);
+        }
+    }
+    Ok(output)
+}
+
+/// resolve HEAD to a commit hash, so the analysis and later updates agree on
+/// where it ended. None if HEAD doesn't point to a commit yet.
+fn resolve_head(repo_path: &str) -> Result<Option<String>> {
+    let args: Vec<String> = ["rev-parse", "--verify", "-q", "HEAD"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let output = run_git(repo_path, &args)?;
+    match output.status.code() {
+        Some(0) => Ok(Some(String::from_utf8(output.stdout)?.trim().to_string())),
+        // --verify -q exits with 1 and no message if HEAD doesn't point to a
+        // commit yet.
+        Some(1) => Ok(None),
+        _ => Err(DepsawError::GitCommandFailed(format!(
+            "git {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))),
+    }
+}
 
+/// whether the commit is in the history of the descendant.
+fn is_ancestor(repo_path: &str, commit: &str, descendant: &str) -> Result<bool> {
+    let args: Vec<String> = ["merge-base", "--is-ancestor", commit, descendant]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let output = run_git(repo_path, &args)?;
+    match output.status.code() {
+        Some(0) => Ok(true),
+        Some(1) => Ok(false),
+        // e.g. the commit no longer exists after a rebase and gc.
+        _ => {
+            debug!(
+                "git {}: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            Ok(false)
+        }
+    }
+}
+
+/// the unix timestamp git's `--since` resolves the date to now.
+fn since_timestamp(repo_path: &str, since: &str) -> Result<i64> {
+    let args = vec!["rev-parse".to_string(), format!("--since={}", since)];
+    let output = run_git(repo_path, &args)?;
+    let stdout = String::from_utf8(output.stdout)?;
+    stdout
+        .trim()
+        .strip_prefix("--max-age=")
+        .and_then(|t| t.parse().ok())
+        .filter(|_| output.status.success())
+        .ok_or_else(|| {
+            DepsawError::GitCommandFailed(format!(
+                "git {}: unexpected output {}{}",
+                args.join(" "),
+                stdout.trim(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        })
+}
+
+/// The history read from `git log`.
+#[derive(Debug, Default)]
+struct Log {
+    files: HashMap<String, GitFile>,
+    /// the renames seen, as a map of old path to newest path.
+    renamed_to: HashMap<String, String>,
+    /// the committer timestamp of each commit.
+    commit_times: HashMap<String, i64>,
+}
+
+/// the arguments of the `git log` reading the history, and its input. Long
+/// lists of path prefixes are passed on stdin instead.
+fn log_invocation(
+    options: &HistoryOptions,
+    revision_range: &str,
+    limit: usize,
+) -> (Vec<String>, Option<String>) {
     // Build command args, conditionally adding --since
     let mut args: Vec<String> = vec![
         "log".to_string(),
-        format!("--format={}%H", DEPSAW_COMMIT_PREFIX).to_string(),
-        "--name-only".to_string(),
+        format!("--format={}%H %ct", DEPSAW_COMMIT_PREFIX),
+        "--name-status".to_string(),
     ];
-    if let Some(since_date) = since {
+    if options.detect_renames {
+        args.push("-M".to_string());
+    } else {
+        args.push("--no-renames".to_string());
+    }
+    if let Some(since_date) = &options.since {
         let arg = format!("--since={}", since_date);
         args.push(arg);
     }
+    args.push(revision_range.to_string());
+    let mut input = None;
+    if !options.path_prefixes.is_empty() {
+        let mut with_paths = args.clone();
+        with_paths.push("--".to_string());
+        with_paths.extend(options.path_prefixes.iter().cloned());
+        if argv::exceeds_limit("git", &with_paths, limit) {
+            // --stdin reads pathspecs after a `--` line.
+            args.push("--stdin".to_string());
+            input = Some(format!("--\n{}\n", options.path_prefixes.join("\n")));
+        } else {
+            args = with_paths;
+        }
+    }
+    (args, input)
+}
 
-    let prog = "git";
-    let cmd = format!("{} {}", prog, args.join(" "));
-    debug!(repo_path, cmd, "running git log");
-    // Run git log command
-    let output = std::process::Command::new(prog)
-        .current_dir(repo_path)
-        .args(&args)
-        .output()?;
-
+fn get_file_commit_history(
+    repo_path: &str,
+    options: &HistoryOptions,
+    revision_range: &str,
+) -> Result<Log> {
+    let (args, input) = log_invocation(options, revision_range, argv::MAX_ARGV_BYTES);
+    let output = run_git_with_input(repo_path, &args, input)?;
     if !output.status.success() {
-        return Err(format!(
-            "Git command {} failed: {}",
-            cmd,
-            String::from_utf8_lossy(&output.stderr)
-        )
-        .into());
+        return Err(DepsawError::GitCommandFailed(format!(
+            "git {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
     }
 
     let output_str = String::from_utf8(output.stdout)?;
-    let mut lines = output_str.lines();
-    lines.next();
+    Ok(parse_name_status_log(&output_str))
+}
+
+/// parse the output of `git log --name-status`, newest commit first.
+///
+/// Renamed files keep their history: commits touching the old path before
+/// the rename are recorded against the newest name of the file. Copies only
+/// record the commit against the destination, as the source still exists.
+///
+/// Commit lines are the prefixed hash, optionally followed by a space and the
+/// committer timestamp.
+fn parse_name_status_log(output: &str) -> Log {
+    let mut file_commits: HashMap<String, GitFile> = HashMap::new();
+    let mut commit_times = HashMap::new();
+    // old path -> the path it was eventually renamed to.
+    let mut renamed_to: HashMap<String, String> = HashMap::new();
+    let resolve = |renamed_to: &HashMap<String, String>, path: &str| -> String {
+        renamed_to
+            .get(path)
+            .cloned()
+            .unwrap_or_else(|| path.to_string())
+    };
 
     let mut commit = "";
-    while let Some(line) = lines.next() {
+    for line in output.lines() {
         if line.is_empty() {
             continue;
         }
-        if line.starts_with(DEPSAW_COMMIT_PREFIX) {
-            commit = line.split(DEPSAW_COMMIT_PREFIX).nth(1).unwrap();
-        } else {
-            file_commits
-                .entry(line.to_string())
-                .or_insert_with(|| GitFile {
-                    commit_history: HashSet::new(),
-                })
-                .commit_history
-                .insert(commit.to_string());
+        if let Some(hash) = line.strip_prefix(DEPSAW_COMMIT_PREFIX) {
+            commit = match hash.split_once(' ') {
+                Some((hash, time)) => {
+                    if let Ok(time) = time.parse::<i64>() {
+                        commit_times.insert(hash.to_string(), time);
+                    }
+                    hash
+                }
+                None => hash,
+            };
+            continue;
         }
+        let mut fields = line.split('\t');
+        let status = fields.next().unwrap_or_default();
+        let path = match (status.chars().next(), fields.next(), fields.next()) {
+            (Some('R'), Some(old), Some(new)) => {
+                // older commits touching the old path are attributed to
+                // the file's newest name. Anything already recorded under
+                // the old path is from newer commits reusing that path.
+                let new = resolve(&renamed_to, new);
+                renamed_to.insert(old.to_string(), new.clone());
+                new
+            }
+            (Some('C'), Some(_), Some(new)) => resolve(&renamed_to, new),
+            (Some(_), Some(path), None) => resolve(&renamed_to, path),
+            _ => {
+                debug!("skipping unrecognized git log line: {}", line);
+                continue;
+            }
+        };
+        file_commits
+            .entry(path)
+            .or_default()
+            .commit_history
+            .insert(commit.to_string());
+    }
+
+    Log {
+        files: file_commits,
+        renamed_to,
+        commit_times,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{commit, commit_at, git, git_repo, TempDir};
+
+    #[test]
+    fn missing_workspace_is_a_git_error() {
+        let err = GitRepo::from_path("/nonexistent/depsaw/workspace", &HistoryOptions::default())
+            .unwrap_err();
+        assert!(matches!(err, DepsawError::GitCommandFailed(_)));
+        assert!(err.to_string().contains("/nonexistent/depsaw/workspace"));
+    }
+
+    fn history(files: &HashMap<String, GitFile>) -> Vec<(&str, Vec<&str>)> {
+        let mut history: Vec<_> = files
+            .iter()
+            .map(|(path, file)| {
+                let mut commits: Vec<_> = file.commit_history.iter().map(|c| c.as_str()).collect();
+                commits.sort();
+                (path.as_str(), commits)
+            })
+            .collect();
+        history.sort();
+        history
+    }
+
+    #[test]
+    fn renames_carry_history_to_the_newest_path() {
+        let log = "depsaw-commit:c3
+M\tb/New.java
+
+depsaw-commit:c2
+R100\ta/Old.java\tb/New.java
+M\ta/Other.java
+
+depsaw-commit:c1
+A\ta/Old.java
+A\ta/Other.java
+";
+        let Log {
+            files, renamed_to, ..
+        } = parse_name_status_log(log);
+        assert_eq!(
+            history(&files),
+            vec![
+                ("a/Other.java", vec!["c1", "c2"]),
+                ("b/New.java", vec!["c1", "c2", "c3"])
+            ]
+        );
+        assert_eq!(
+            renamed_to,
+            HashMap::from([("a/Old.java".to_string(), "b/New.java".to_string())])
+        );
+    }
+
+    #[test]
+    fn chained_renames_resolve_to_the_last_name() {
+        let log = "depsaw-commit:c3
+R090\tb/B.java\tc/C.java
+depsaw-commit:c2
+R100\ta/A.java\tb/B.java
+depsaw-commit:c1
+A\ta/A.java
+";
+        let Log {
+            files, renamed_to, ..
+        } = parse_name_status_log(log);
+        assert_eq!(history(&files), vec![("c/C.java", vec!["c1", "c2", "c3"])]);
+        assert_eq!(renamed_to["a/A.java"], "c/C.java");
+        assert_eq!(renamed_to["b/B.java"], "c/C.java");
+    }
+
+    #[test]
+    fn copies_only_record_the_destination() {
+        let log = "depsaw-commit:c2
+C075\ta/A.java\tb/B.java
+depsaw-commit:c1
+A\ta/A.java
+";
+        let Log {
+            files, renamed_to, ..
+        } = parse_name_status_log(log);
+        assert_eq!(
+            history(&files),
+            vec![("a/A.java", vec!["c1"]), ("b/B.java", vec!["c2"])]
+        );
+        assert!(renamed_to.is_empty());
+    }
+
+    #[test]
+    fn deletes_and_modifications_are_recorded() {
+        let log = "depsaw-commit:c2
+D\ta/A.java
+M\ta/B.java
+depsaw-commit:c1
+A\ta/A.java
+A\ta/B.java
+unexpected line
+";
+        let files = parse_name_status_log(log).files;
+        assert_eq!(
+            history(&files),
+            vec![
+                ("a/A.java", vec!["c1", "c2"]),
+                ("a/B.java", vec!["c1", "c2"])
+            ]
+        );
+    }
+
+    #[test]
+    fn no_renames_keeps_paths_apart() {
+        let dir = git_repo("renames");
+        let c1 = commit(&dir, &[("a/A.java", "a\nb\nc\n")], "add a");
+        git(&dir, &["mv", "a/A.java", "a/B.java"]);
+        let c2 = commit(&dir, &[], "move a");
+        let mut both = vec![c1.as_str(), c2.as_str()];
+        both.sort();
+
+        let renames = GitRepo::from_path(dir.path_str(), &HistoryOptions::default()).unwrap();
+        assert_eq!(history(&renames.files), vec![("a/B.java", both.clone())]);
+        let options = HistoryOptions {
+            detect_renames: false,
+            ..Default::default()
+        };
+        let no_renames = GitRepo::from_path(dir.path_str(), &options).unwrap();
+        assert_eq!(
+            history(&no_renames.files),
+            vec![("a/A.java", both), ("a/B.java", vec![c2.as_str()])]
+        );
+    }
+
+    #[test]
+    fn repository_without_commits_has_an_empty_history() {
+        let dir = git_repo("unborn");
+        let repo = GitRepo::from_path(dir.path_str(), &HistoryOptions::default()).unwrap();
+        assert!(repo.is_empty());
+        assert_eq!(repo.last_commit, None);
+
+        // the first commit is picked up by updating the empty analysis.
+        let head = commit(&dir, &[("a/A.java", "a")], "add a");
+        let repo = repo
+            .update(dir.path_str(), &HistoryOptions::default())
+            .unwrap();
+        assert_eq!(
+            repo.files["a/A.java"].commit_history,
+            HashSet::from([head.clone()])
+        );
+        assert_eq!(repo.last_commit, Some(head));
+    }
+
+    #[test]
+    fn commits_outside_since_give_an_empty_history() {
+        let dir = git_repo("since");
+        let head = commit_at(
+            &dir,
+            &[("a/A.java", "a")],
+            "add a",
+            Some("2001-01-01T00:00:00"),
+        );
+        let options = HistoryOptions {
+            since: Some("2002-01-01 00:00:00 +0000".to_string()),
+            ..Default::default()
+        };
+        let repo = GitRepo::from_path(dir.path_str(), &options).unwrap();
+        assert!(repo.is_empty());
+        assert_eq!(repo.last_commit, Some(head));
+        assert_eq!(repo.window.unwrap().start, 1_009_843_200);
+    }
+
+    #[test]
+    fn window_starts_at_the_oldest_commit_without_since() {
+        let dir = git_repo("window");
+        commit_at(
+            &dir,
+            &[("a/A.java", "a")],
+            "add a",
+            Some("@1600000000 +0000"),
+        );
+        commit(&dir, &[("b/B.java", "b")], "add b");
+        let repo = GitRepo::from_path(dir.path_str(), &HistoryOptions::default()).unwrap();
+        let window = repo.window.unwrap();
+        assert_eq!(window.start, 1_600_000_000);
+        assert!(window.days() > 365.0);
+        // filtering keeps the window of history read.
+        assert_eq!(repo.filter_paths(&["b".to_string()]).window, Some(window));
     }
 
-    Ok(file_commits)
+    /// a git date `days` days before now.
+    fn days_ago(days: i64) -> String {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        format!("@{} +0000", now - days * 24 * 60 * 60)
+    }
+
+    /// update the analysis and check it matches a full analysis.
+    fn assert_update_is_full(dir: &TempDir, repo: GitRepo, options: &HistoryOptions) -> GitRepo {
+        let updated = repo.update(dir.path_str(), options).unwrap();
+        let full = GitRepo::from_path(dir.path_str(), options).unwrap();
+        assert_eq!(history(&updated.files), history(&full.files));
+        assert_eq!(updated.last_commit, full.last_commit);
+        assert_eq!(updated.options, full.options);
+        assert_eq!(updated.commit_times, full.commit_times);
+        updated
+    }
+
+    #[test]
+    fn update_matches_a_full_analysis() {
+        let dir = git_repo("update");
+        commit(
+            &dir,
+            &[("a/A.java", "a\nb\nc\n"), ("b/B.java", "b")],
+            "add a and b",
+        );
+        let options = HistoryOptions::default();
+        let repo = GitRepo::from_path(dir.path_str(), &options).unwrap();
+
+        git(&dir, &["mv", "a/A.java", "a/Renamed.java"]);
+        commit(&dir, &[("b/B.java", "b2")], "rename a");
+        commit(
+            &dir,
+            &[("a/Renamed.java", "a\nb\nc\nd\n"), ("bazel-out/x", "x")],
+            "edit",
+        );
+        let repo = assert_update_is_full(&dir, repo, &options);
+        assert!(repo.files.contains_key("a/Renamed.java"));
+        assert!(!repo.files.contains_key("bazel-out/x"));
+    }
+
+    #[test]
+    fn up_to_date_update_still_applies_exclusions() {
+        let dir = git_repo("up-to-date");
+        commit(&dir, &[("a/A.java", "a"), ("vendor/V.java", "v")], "add");
+        let options = HistoryOptions::default();
+        let repo = GitRepo::from_path(dir.path_str(), &options).unwrap();
+        assert!(repo.files.contains_key("vendor/V.java"));
+
+        // .bazelignore isn't committed, so HEAD doesn't move.
+        dir.write(".bazelignore", "vendor\n");
+        let repo = assert_update_is_full(&dir, repo, &options);
+        assert!(!repo.files.contains_key("vendor/V.java"));
+    }
+
+    #[test]
+    fn update_expires_commits_outside_a_relative_since() {
+        let dir = git_repo("since-window");
+        commit_at(&dir, &[("a/A.java", "a")], "old", Some(&days_ago(20)));
+        commit_at(&dir, &[("b/B.java", "b")], "older", Some(&days_ago(10)));
+        commit_at(&dir, &[("b/B.java", "b2")], "recent", Some(&days_ago(1)));
+        let options = HistoryOptions {
+            since: Some("5 days ago".to_string()),
+            ..Default::default()
+        };
+        // an analysis from when the window still covered every commit.
+        let mut repo = GitRepo::from_path(
+            dir.path_str(),
+            &HistoryOptions {
+                since: Some("30 days ago".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(repo.files.len(), 2);
+        repo.options = Some(options.clone());
+
+        let repo = assert_update_is_full(&dir, repo, &options);
+        assert_eq!(history(&repo.files).len(), 1);
+        assert_eq!(repo.files["b/B.java"].commit_history.len(), 1);
+    }
+
+    #[test]
+    fn update_rebuilds_after_a_rebase() {
+        let dir = git_repo("rebase");
+        commit(&dir, &[("a/A.java", "a")], "add a");
+        commit(&dir, &[("b/B.java", "b")], "add b");
+        let options = HistoryOptions::default();
+        let repo = GitRepo::from_path(dir.path_str(), &options).unwrap();
+
+        git(&dir, &["reset", "-q", "--hard", "HEAD~1"]);
+        commit(&dir, &[("c/C.java", "c")], "add c instead");
+        let repo = assert_update_is_full(&dir, repo, &options);
+        assert!(!repo.files.contains_key("b/B.java"));
+    }
+
+    #[test]
+    fn update_rebuilds_with_other_options() {
+        let dir = git_repo("options");
+        commit(&dir, &[("a/A.java", "a"), ("b/B.java", "b")], "add");
+        let repo = GitRepo::from_path(dir.path_str(), &HistoryOptions::default()).unwrap();
+        let prefixed = HistoryOptions {
+            path_prefixes: vec!["a".to_string()],
+            ..Default::default()
+        };
+        let repo = assert_update_is_full(&dir, repo, &prefixed);
+        assert_eq!(history(&repo.files).len(), 1);
+
+        // filtering records the prefixes, so updates stay filtered.
+        let repo = GitRepo::from_path(dir.path_str(), &HistoryOptions::default())
+            .unwrap()
+            .filter_paths(&prefixed.path_prefixes);
+        assert_eq!(repo.options.as_ref(), Some(&prefixed));
+        let no_renames = HistoryOptions {
+            detect_renames: false,
+            ..prefixed.clone()
+        };
+        assert_update_is_full(&dir, repo, &no_renames);
+    }
+
+    fn glob(glob: &str, path: &str) -> bool {
+        glob_matches(glob.as_bytes(), path.as_bytes())
+    }
+
+    #[test]
+    fn star_matches_within_a_component() {
+        assert!(glob("*.java", "A.java"));
+        assert!(glob("a/*.java", "a/A.java"));
+        assert!(glob("a/*", "a/"));
+        assert!(!glob("a/*.java", "a/b/A.java"));
+        assert!(glob("a/?.java", "a/A.java"));
+        assert!(!glob("a?b", "a/b"));
+    }
+
+    #[test]
+    fn double_star_matches_across_components() {
+        assert!(glob("**/gen/*.java", "gen/A.java"));
+        assert!(glob("**/gen/*.java", "a/b/gen/A.java"));
+        assert!(glob("a/**", "a/b/c/A.java"));
+        assert!(glob("a/**/A.java", "a/A.java"));
+        assert!(glob("a/**/A.java", "a/b/c/A.java"));
+        assert!(!glob("a/**/A.java", "b/A.java"));
+    }
+
+    #[test]
+    fn globs_are_anchored_at_the_workspace_root() {
+        assert!(!glob("*.java", "a/A.java"));
+        assert!(!glob("gen", "a/gen"));
+        assert!(!glob("a/*.java", "a/A.javax"));
+        assert!(!glob("a/A", "a/A.java"));
+    }
+
+    fn exclusions(dir: &TempDir, globs: &[&str]) -> PathExclusions {
+        let globs: Vec<String> = globs.iter().map(|g| g.to_string()).collect();
+        PathExclusions::load(dir.path_str(), &globs).unwrap()
+    }
+
+    #[test]
+    fn globs_exclude_matching_directories() {
+        let dir = TempDir::new("globs");
+        let exclusions = exclusions(&dir, &["vendor/", "**/generated"]);
+        assert!(exclusions.excludes("vendor/lib/A.java"));
+        assert!(exclusions.excludes("vendor"));
+        assert!(!exclusions.excludes("vendored/A.java"));
+        assert!(exclusions.excludes("a/generated/A.java"));
+        assert!(!exclusions.excludes("a/generated.java"));
+    }
+
+    #[test]
+    fn bazel_symlinks_and_bazelignore_are_excluded() {
+        let dir = TempDir::new("bazelignore");
+        dir.write(".bazelignore", "# vendored code\nthird_party/vendor/\n\n");
+        let exclusions = exclusions(&dir, &[]);
+        for path in ["bazel-out/k8/A.java", "bazel-bin/a", "third_party/vendor/a"] {
+            assert!(exclusions.excludes(path), "{} is excluded", path);
+        }
+        assert!(!exclusions.excludes("bazel-outputs/a"));
+        assert!(!exclusions.excludes("third_party/other/a"));
+    }
+
+    #[test]
+    fn execroot_symlink_is_named_after_the_declared_workspace() {
+        let dir = TempDir::new("checkout");
+        // without a declared name, the directory name isn't used.
+        let name = dir.path().file_name().unwrap().to_str().unwrap();
+        assert!(!exclusions(&dir, &[]).excludes(&format!("bazel-{}/a", name)));
+
+        dir.write("WORKSPACE", "workspace(\n    name = 'legacy',\n)\n");
+        assert!(exclusions(&dir, &[]).excludes("bazel-legacy/a"));
+
+        // MODULE.bazel takes precedence over WORKSPACE.
+        dir.write(
+            "MODULE.bazel",
+            "module(name = \"my_repo\", version = \"1.0\")\n\nbazel_dep(name = \"rules_java\", version = \"7.0\")\n",
+        );
+        let exclusions = exclusions(&dir, &[]);
+        assert!(exclusions.excludes("bazel-my_repo/a"));
+        assert!(!exclusions.excludes("bazel-legacy/a"));
+        assert!(!exclusions.excludes("bazel-rules_java/a"));
+    }
+
+    #[test]
+    fn declared_name_ignores_other_calls() {
+        assert_eq!(
+            declared_name(
+                "bazel_dep(name = \"x\")\nmodule(repo_name = \"r\", name = \"m\")",
+                "module"
+            ),
+            Some("m".to_string())
+        );
+        assert_eq!(declared_name("submodule(name = \"x\")", "module"), None);
+        assert_eq!(declared_name("module(version = \"1.0\")", "module"), None);
+    }
+
+    #[test]
+    fn long_pathspecs_are_passed_on_stdin() {
+        let dir = git_repo("pathspecs");
+        commit(
+            &dir,
+            &[("a/A.java", "a"), ("b/B.java", "b"), ("c/C.java", "c")],
+            "add",
+        );
+        let options = HistoryOptions {
+            path_prefixes: vec!["a".to_string(), "b".to_string()],
+            ..Default::default()
+        };
+        let (direct, input) = log_invocation(&options, "HEAD", argv::MAX_ARGV_BYTES);
+        assert_eq!(direct[direct.len() - 3..], ["--", "a", "b"]);
+        assert!(input.is_none());
+
+        let (args, input) = log_invocation(&options, "HEAD", 32);
+        assert_eq!(args.last().unwrap(), "--stdin");
+        assert!(!args.contains(&"--".to_string()));
+        assert_eq!(input.as_deref(), Some("--\na\nb\n"));
+
+        let read = |args: &[String], input: Option<String>| {
+            let output = run_git_with_input(dir.path_str(), args, input).unwrap();
+            assert!(output.status.success());
+            let mut paths: Vec<String> =
+                parse_name_status_log(&String::from_utf8(output.stdout).unwrap())
+                    .files
+                    .into_keys()
+                    .collect();
+            paths.sort();
+            paths
+        };
+        assert_eq!(read(&args, input), vec!["a/A.java", "b/B.java"]);
+        assert_eq!(read(&direct, None), vec!["a/A.java", "b/B.java"]);
+    }
+
+    /// a repository with tracked, untracked and ignored files, ignored by
+    /// the root .gitignore, a nested one and .git/info/exclude.
+    fn relevance_repo() -> TempDir {
+        let dir = git_repo("relevance");
+        commit(
+            &dir,
+            &[
+                ("a/A.java", "a"),
+                (".gitignore", "*.log\n"),
+                ("a/.gitignore", "gen/\n"),
+            ],
+            "add",
+        );
+        dir.write("a/tracked.log", "tracked despite the ignore");
+        git(&dir, &["add", "-f", "a/tracked.log"]);
+        git(&dir, &["commit", "-q", "-m", "force add"]);
+        dir.write(".git/info/exclude", "scratch/\n");
+        for path in [
+            "a/Notes.java",
+            "a/gen/G.java",
+            "gen/G.java",
+            "debug.log",
+            "scratch/S.java",
+        ] {
+            dir.write(path, "untracked");
+        }
+        dir
+    }
+
+    #[test]
+    fn relevance_covers_nested_ignores_and_info_exclude() {
+        let dir = relevance_repo();
+        let mut relevance = FileRelevance::new(dir.path_str()).unwrap();
+        let cases = [
+            ("a/A.java", FileStatus::Tracked),
+            ("a/tracked.log", FileStatus::Tracked),
+            ("a/Notes.java", FileStatus::Untracked),
+            // the nested .gitignore only applies under a/.
+            ("a/gen/G.java", FileStatus::Ignored),
+            ("gen/G.java", FileStatus::Untracked),
+            ("debug.log", FileStatus::Ignored),
+            ("scratch/S.java", FileStatus::Ignored),
+            ("a/Missing.java", FileStatus::Untracked),
+        ];
+        let paths: Vec<String> = cases.iter().map(|(path, _)| path.to_string()).collect();
+        relevance.check(&paths).unwrap();
+        // checked paths are cached, so git isn't needed to answer for them.
+        let git_dir = dir.path().join(".git");
+        let moved_git_dir = dir.path().join("moved.git");
+        std::fs::rename(&git_dir, &moved_git_dir).unwrap();
+        for (path, status) in cases {
+            assert_eq!(relevance.status(path).unwrap(), status, "{}", path);
+        }
+        // other paths are checked on their own.
+        std::fs::rename(&moved_git_dir, &git_dir).unwrap();
+        assert_eq!(relevance.status("other.log").unwrap(), FileStatus::Ignored);
+    }
+
+    #[test]
+    fn every_file_is_relevant_outside_a_repository() {
+        let dir = TempDir::new("not-a-repo");
+        dir.write("debug.log", "log");
+        let mut relevance = FileRelevance::new(dir.path_str()).unwrap();
+        assert_eq!(relevance.status("debug.log").unwrap(), FileStatus::Tracked);
+    }
 }
@@ -0,0 +1,248 @@
+//! Opening user-provided input files.
+//!
+//! Inputs can be read from stdin by passing `-` as the path, and gzipped
+//! inputs are decompressed transparently. Gzip is detected by its magic
+//! bytes rather than the file extension, and decompressed as a stream by
+//! piping through `gzip -dc`, so the decompressed payload never has to fit in
+//! memory or on disk. This needs `gzip` on the PATH, but only when an input
+//! is gzipped.
+use super::error::{DepsawError, Result};
+use super::metrics;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::thread::JoinHandle;
+use tracing::debug;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// open the input at the given path, or stdin for `-`.
+pub fn open(path: &str) -> Result<Box<dyn BufRead>> {
+    open_with_stdin(path, || Box::new(BufReader::new(std::io::stdin())))
+}
+
+fn open_with_stdin(
+    path: &str,
+    stdin: impl FnOnce() -> Box<dyn BufRead + Send>,
+) -> Result<Box<dyn BufRead>> {
+    let mut reader: Box<dyn BufRead + Send> = if path == "-" {
+        stdin()
+    } else {
+        Box::new(BufReader::new(File::open(path)?))
+    };
+    if !reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        return Ok(reader);
+    }
+
+    debug!("decompressing gzipped input {}", path);
+    Ok(Box::new(BufReader::new(Decompressor::spawn(path, reader)?)))
+}
+
+/// the output of a gzip process, which errors at the end of the stream if
+/// reading the compressed input or gzip failed.
+struct Decompressor {
+    path: String,
+    child: Child,
+    stdout: ChildStdout,
+    /// the thread feeding the compressed input to gzip, joined at the end of
+    /// the stream.
+    feeder: Option<JoinHandle<std::io::Result<u64>>>,
+}
+
+impl Decompressor {
+    fn spawn(path: &str, mut reader: Box<dyn BufRead + Send>) -> Result<Decompressor> {
+        metrics::record_subprocess();
+        let mut child = Command::new("gzip")
+            .arg("-dc")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                DepsawError::IoError(std::io::Error::new(
+                    e.kind(),
+                    format!("unable to run gzip to decompress {}: {}", path, e),
+                ))
+            })?;
+        // feed the compressed input from a separate thread, so gzip never
+        // blocks writing its output while we block writing its input.
+        let mut stdin = child.stdin.take().unwrap();
+        let feeder = std::thread::spawn(move || std::io::copy(&mut reader, &mut stdin));
+        let stdout = child.stdout.take().unwrap();
+        Ok(Decompressor {
+            path: path.to_string(),
+            child,
+            stdout,
+            feeder: Some(feeder),
+        })
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        let status = self.child.wait()?;
+        if let Some(feeder) = self.feeder.take() {
+            match feeder.join() {
+                // gzip stopped reading, its exit status says why.
+                Ok(Err(e)) if e.kind() == std::io::ErrorKind::BrokenPipe => {}
+                Ok(Err(e)) => {
+                    return Err(std::io::Error::new(
+                        e.kind(),
+                        format!("unable to read {}: {}", self.path, e),
+                    ));
+                }
+                Ok(Ok(_)) => {}
+                Err(_) => {
+                    return Err(std::io::Error::other(format!(
+                        "reading {} panicked",
+                        self.path
+                    )));
+                }
+            }
+        }
+        if !status.success() {
+            return Err(std::io::Error::other(format!(
+                "gzip failed to decompress {}: {}",
+                self.path, status
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Read for Decompressor {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.stdout.read(buf)?;
+        if read == 0 && !buf.is_empty() {
+            self.finish()?;
+        }
+        Ok(read)
+    }
+}
+
+impl Drop for Decompressor {
+    /// stop and reap gzip when the input wasn't read to the end. The feeder
+    /// can be blocked reading stdin, so it is left to stop on its own once
+    /// gzip's stdin is closed.
+    fn drop(&mut self) {
+        if self.feeder.is_some() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::io::{Cursor, Write};
+
+    fn gzip(content: &[u8]) -> Vec<u8> {
+        let mut child = Command::new("gzip")
+            .arg("-c")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(content).unwrap();
+        child.wait_with_output().unwrap().stdout
+    }
+
+    fn read_all(mut reader: Box<dyn BufRead>) -> std::io::Result<String> {
+        let mut content = String::new();
+        reader.read_to_string(&mut content)?;
+        Ok(content)
+    }
+
+    #[test]
+    fn plain_files_are_read_as_is() {
+        let dir = TempDir::new("plain");
+        dir.write("query.ndjson", "{}\n{}\n");
+        let reader = open(&dir.join("query.ndjson")).unwrap();
+        assert_eq!(read_all(reader).unwrap(), "{}\n{}\n");
+    }
+
+    #[test]
+    fn gzipped_files_are_decompressed() {
+        let dir = TempDir::new("gzip");
+        let path = dir.join("query.ndjson.gz");
+        let compressed = gzip(b"{}\n{}\n");
+        assert!(compressed.starts_with(&GZIP_MAGIC));
+        std::fs::write(&path, compressed).unwrap();
+        assert_eq!(read_all(open(&path).unwrap()).unwrap(), "{}\n{}\n");
+    }
+
+    #[test]
+    fn corrupt_gzip_errors_at_the_end_of_the_stream() {
+        let dir = TempDir::new("corrupt");
+        let path = dir.join("query.ndjson.gz");
+        let mut compressed = gzip(b"{}\n{}\n");
+        compressed.truncate(compressed.len() / 2);
+        std::fs::write(&path, compressed).unwrap();
+        let err = read_all(open(&path).unwrap()).unwrap_err();
+        assert!(err.to_string().contains(&path));
+    }
+
+    #[test]
+    fn dash_reads_stdin() {
+        let stdin = || -> Box<dyn BufRead + Send> { Box::new(Cursor::new(b"{}\n".to_vec())) };
+        assert_eq!(
+            read_all(open_with_stdin("-", stdin).unwrap()).unwrap(),
+            "{}\n"
+        );
+        let stdin = || -> Box<dyn BufRead + Send> { Box::new(Cursor::new(gzip(b"{}\n"))) };
+        assert_eq!(
+            read_all(open_with_stdin("-", stdin).unwrap()).unwrap(),
+            "{}\n"
+        );
+    }
+
+    /// a gzip stream that fails partway through, like a disk read error.
+    struct FailingInput {
+        header: Option<Vec<u8>>,
+    }
+
+    impl Read for FailingInput {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.header.take() {
+                Some(header) => {
+                    buf[..header.len()].copy_from_slice(&header);
+                    Ok(header.len())
+                }
+                None => Err(std::io::Error::other("input/output error")),
+            }
+        }
+    }
+
+    #[test]
+    fn errors_reading_the_compressed_input_are_reported() {
+        let stdin = || -> Box<dyn BufRead + Send> {
+            let header = gzip(b"{}\n{}\n")[..10].to_vec();
+            Box::new(BufReader::new(FailingInput {
+                header: Some(header),
+            }))
+        };
+        let err = read_all(open_with_stdin("-", stdin).unwrap()).unwrap_err();
+        assert_eq!(err.to_string(), "unable to read -: input/output error");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn gzip_is_reaped_when_the_input_is_not_read_to_the_end() {
+        let content = "{}\n".repeat(1 << 20);
+        let input: Box<dyn BufRead + Send> = Box::new(Cursor::new(gzip(content.as_bytes())));
+        let mut decompressor = Decompressor::spawn("-", input).unwrap();
+        let pid = decompressor.child.id();
+        decompressor.read_exact(&mut [0; 3]).unwrap();
+        drop(decompressor);
+        // a child that exited but wasn't waited for would still be listed.
+        assert!(!std::path::Path::new(&format!("/proc/{}", pid)).exists());
+    }
+
+    #[test]
+    fn missing_files_are_an_error() {
+        let dir = TempDir::new("missing");
+        assert!(matches!(
+            open(&dir.join("nope.ndjson")),
+            Err(DepsawError::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound
+        ));
+    }
+}
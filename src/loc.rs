@@ -0,0 +1,155 @@
+//! Counting lines of code owned by bazel targets.
+//!
+//! Lines are counted the same way as `wc -l`: by the number of newline bytes
+//! in the file.
+use super::bazel::{self, BazelDependencyGraph};
+use super::error::Result;
+use super::workspace::{SkippedPath, Workspace};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use tracing::info;
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct LocStats {
+    /// number of source files whose lines were counted.
+    pub files_counted: usize,
+    /// number of source files that could not be found in the workspace, and
+    /// counted as zero lines.
+    pub files_missing: usize,
+    /// number of source files that were not tracked by git, and counted as
+    /// zero lines.
+    pub files_untracked: usize,
+    /// number of source files that were ignored by git, and counted as zero
+    /// lines.
+    pub files_ignored: usize,
+    /// source files that could not be read, and counted as zero lines.
+    pub skipped_paths: Vec<SkippedPath>,
+}
+
+pub fn count_lines(path: &Path) -> std::io::Result<usize> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut lines = 0;
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        lines += buf.iter().filter(|b| **b == b'\n').count();
+        let len = buf.len();
+        reader.consume(len);
+    }
+    Ok(lines)
+}
+
+/// count the lines of the source files owned directly by each of the given
+/// targets, resolved relative to the workspace root.
+pub fn lines_by_target<'a>(
+    workspace: &mut Workspace,
+    deps_graph: &BazelDependencyGraph,
+    targets: impl IntoIterator<Item = &'a String>,
+) -> Result<(HashMap<String, usize>, LocStats)> {
+    let mut stats = LocStats::default();
+    let mut lines_by_target = HashMap::new();
+    let paths_by_target: Vec<(&String, Vec<String>)> = targets
+        .into_iter()
+        .map(|target| {
+            let paths = deps_graph
+                .rules_by_label
+                .get(target)
+                .map(|entry| {
+                    entry
+                        .source_files
+                        .iter()
+                        .filter_map(|source_file| bazel::label_to_path(source_file))
+                        .collect()
+                })
+                .unwrap_or_default();
+            (target, paths)
+        })
+        .collect();
+    let all_paths: Vec<String> = paths_by_target
+        .iter()
+        .flat_map(|(_, paths)| paths.iter().cloned())
+        .collect();
+    workspace.check_relevance(&all_paths)?;
+    for (target, paths) in paths_by_target {
+        let mut total_lines = 0;
+        for relative_path in paths {
+            if !workspace.is_relevant(&relative_path)? {
+                continue;
+            }
+            if let Some(lines) = workspace.read_with(&relative_path, count_lines)? {
+                stats.files_counted += 1;
+                total_lines += lines;
+            }
+        }
+        lines_by_target.insert(target.clone(), total_lines);
+    }
+    stats.files_missing = workspace.missing_paths();
+    stats.files_untracked = workspace.untracked_paths();
+    stats.files_ignored = workspace.ignored_paths();
+    stats.skipped_paths = workspace.skipped_paths().to_vec();
+    info!(
+        "counted lines in {} source files, {} missing, {} untracked, {} ignored, {} skipped",
+        stats.files_counted,
+        stats.files_missing,
+        stats.files_untracked,
+        stats.files_ignored,
+        stats.skipped_paths.len()
+    );
+    Ok((lines_by_target, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{commit, git_repo, graph, rule, source};
+
+    #[test]
+    fn only_tracked_files_are_counted() {
+        let dir = git_repo("loc");
+        commit(
+            &dir,
+            &[("a/A.java", "1\n2\n"), (".gitignore", "*.gen.java\n")],
+            "add",
+        );
+        dir.write("a/Scratch.java", "1\n2\n3\n");
+        dir.write("a/B.gen.java", "1\n2\n3\n4\n");
+        dir.write(".git/info/exclude", "a/Local.java\n");
+        dir.write("a/Local.java", "1\n");
+        let deps_graph = graph(&[
+            rule(
+                "//a:lib",
+                "java_library",
+                &[
+                    "//a:A.java",
+                    "//a:Scratch.java",
+                    "//a:B.gen.java",
+                    "//a:Local.java",
+                    "//a:Missing.java",
+                ],
+            ),
+            source("//a:A.java"),
+            source("//a:Scratch.java"),
+            source("//a:B.gen.java"),
+            source("//a:Local.java"),
+            source("//a:Missing.java"),
+        ]);
+        let mut workspace = Workspace::new(dir.path_str(), false);
+        let (lines, stats) =
+            lines_by_target(&mut workspace, &deps_graph, &["//a:lib".to_string()]).unwrap();
+        assert_eq!(lines["//a:lib"], 2);
+        assert_eq!(
+            (
+                stats.files_counted,
+                stats.files_missing,
+                stats.files_untracked,
+                stats.files_ignored
+            ),
+            (1, 1, 1, 2)
+        );
+    }
+}
@@ -1,43 +1,31 @@
+use algorithms::trigger_scores::ResolvedTarget;
 use clap::Parser;
-use rkyv;
-use std::error::Error;
-use std::fs::File;
-use std::io::Write;
+use error::DepsawError;
+use std::collections::HashMap;
 
 mod algorithms;
+mod analysis_file;
+mod argv;
 mod bazel;
+mod cost;
+mod directories;
+mod dot;
+mod error;
 mod git;
+mod input;
+mod loc;
+mod metadata;
+mod metrics;
 mod operations;
-use tracing::info;
-use tracing_subscriber;
+mod output;
+mod rng;
+#[cfg(test)]
+mod testing;
+mod update_check;
+mod workspace;
+use tracing::{info, warn};
 use tracing_subscriber::filter::LevelFilter;
 
-// we declare a macro since the Serializable trait
-// cannot be used as a function argument.
-macro_rules! serialize {
-    ( $object:expr, $format:expr) => {
-        match $format.as_str() {
-            "yaml" => {
-                let yaml_output = serde_yaml::to_string(&$object)?;
-                println!("{}", yaml_output);
-            }
-            "csv" => {
-                let mut wtr = csv::WriterBuilder::new()
-                    .has_headers(false)
-                    .from_writer(std::io::stdout());
-                // Serialize each target as a row
-                for item in $object {
-                    wtr.serialize(item)?;
-                }
-                wtr.flush()?;
-            }
-            _ => {
-                panic!("Unsupported format: {}", $format);
-            }
-        }
-    };
-}
-
 #[derive(Parser)]
 #[command(
     author,
@@ -45,6 +33,26 @@ macro_rules! serialize {
     about = "Analyzes and identifies removable Bazel dependencies"
 )]
 struct Args {
+    /// Log a summary of peak memory, cpu time, subprocesses and analysis file
+    /// IO at the end of the run
+    #[arg(long, global = true, default_value_t = true, action = clap::ArgAction::Set)]
+    resource_summary: bool,
+
+    /// Write the metadata of the run, including its resource usage, to this
+    /// file as JSON
+    #[arg(long, global = true)]
+    metadata_output: Option<String>,
+
+    /// Seed every random choice with this, to reproduce a run. Without it,
+    /// a seed is picked and logged
+    #[arg(long, global = true)]
+    seed: Option<u64>,
+
+    /// Whether to check for a newer release of depsaw. `auto` checks at most
+    /// once a week, and never in CI or when DEPSAW_NO_UPDATE_CHECK is set
+    #[arg(long, global = true, value_enum, default_value_t = update_check::UpdateCheck::Never)]
+    update_check: update_check::UpdateCheck,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -60,15 +68,76 @@ enum Commands {
         #[command(subcommand)]
         command: PrecalculateCommands,
     },
+    /// Check whether a newer release of depsaw is available
+    CheckUpdate,
+    /// Answer questions about a precalculated bazel dependency graph
+    Query {
+        /// Path to the bazel analysis file
+        #[arg(long, required = true)]
+        bazel_analysis_file: String,
+
+        #[command(flatten)]
+        output: output::OutputArgs,
+
+        #[command(subcommand)]
+        operation: QueryCommands,
+    },
+    /// Summarize the external repositories a precalculated bazel
+    /// dependency graph refers to
+    Stats {
+        /// Path to the bazel analysis file
+        #[arg(long, required = true)]
+        bazel_analysis_file: String,
+
+        #[command(flatten)]
+        output: output::OutputArgs,
+    },
+    /// Compare the trigger scores of two analyses
+    ScoreDiff {
+        /// trigger-scores-map results of the earlier analysis, as JSON or
+        /// YAML
+        #[arg(long, required = true)]
+        baseline: String,
+
+        /// trigger-scores-map results of the later analysis, as JSON or YAML
+        #[arg(long, required = true)]
+        current: String,
+
+        /// The --metadata-output of the baseline analysis, which records its
+        /// window of history
+        #[arg(long)]
+        baseline_metadata: Option<String>,
+
+        /// The --metadata-output of the current analysis
+        #[arg(long)]
+        current_metadata: Option<String>,
+
+        /// Check that both analyses cover windows of history of the same
+        /// length, and also report the deltas per day of history
+        #[arg(long)]
+        align_window: bool,
+
+        /// Fail instead of warning when the windows can't be aligned
+        #[arg(long, requires = "align_window")]
+        strict_align: bool,
+
+        /// How many days the lengths of the windows may differ by
+        #[arg(long, default_value_t = 0.5)]
+        window_tolerance_days: f64,
+
+        #[command(flatten)]
+        output: output::OutputArgs,
+    },
     /// Run analysis algorithms
     Analyze {
         /// Path to the workspace root
         #[arg(long)]
         workspace_root: Option<String>,
 
-        /// The target to analyze
+        /// The target to analyze. Repeatable for trigger-scores-map, which
+        /// unions the results. Required by every algorithm except dir-scores
         #[arg(long)]
-        target: String,
+        target: Vec<String>,
 
         /// Specified, via git's `since` format, which commits to evaluate
         #[arg(long)]
@@ -82,9 +151,32 @@ enum Commands {
         #[arg(long)]
         bazel_analysis_file: Option<String>,
 
-        /// The format to output the results in
-        #[arg(long, default_value = "yaml")]
-        format: String,
+        /// Path to saved `bazel query --output streamed_jsonproto` output to
+        /// use instead of running bazel. May be gzipped, or `-` for stdin
+        #[arg(long, conflicts_with = "bazel_analysis_file")]
+        bazel_query_output: Option<String>,
+
+        /// Fail if any line of the bazel query output cannot be parsed
+        #[arg(long)]
+        strict: bool,
+
+        /// Fail if any workspace file cannot be read, instead of skipping it
+        /// and reporting it
+        #[arg(long)]
+        strict_io: bool,
+
+        #[command(flatten)]
+        output: output::OutputArgs,
+
+        /// Run the analysis twice, the second time with the targets in a
+        /// seeded random order, and fail if the results differ
+        #[arg(long)]
+        determinism_check: bool,
+
+        /// Which targets to report, by visibility. Filtered targets still
+        /// count towards the rebuilds of the targets that depend on them
+        #[arg(long, value_enum, default_value_t = OnlyVisibility::Any)]
+        only_visibility: OnlyVisibility,
 
         #[command(subcommand)]
         algorithm: AnalyzeCommands,
@@ -102,133 +194,620 @@ enum PrecalculateCommands {
         /// The maximum number of commit history to consider
         #[arg(long)]
         since: Option<String>,
+
+        /// Treat renamed files as unrelated paths instead of carrying their
+        /// history over to the new path
+        #[arg(long)]
+        no_renames: bool,
+
+        /// Only record files under this path, relative to the workspace
+        /// root. Can be repeated.
+        #[arg(long)]
+        path_prefix: Vec<String>,
+
+        /// Leave out paths matching this glob, relative to the workspace
+        /// root. `*` matches within a directory and `**` across directories.
+        /// Can be repeated. bazel's convenience symlinks and directories in
+        /// `.bazelignore` are always left out.
+        #[arg(long)]
+        exclude_path: Vec<String>,
+
+        /// Update the existing analysis at --output with commits made since
+        /// it was written, instead of walking the full history. Falls back
+        /// to a full analysis if the file can't be read, was written with
+        /// other options, or its last commit is no longer in HEAD's history
+        #[arg(long)]
+        update: bool,
+    },
+    /// Trim an existing git analysis file to files under the given paths
+    FilterGitRepo {
+        /// Path to the git analysis file to trim
+        #[arg(long, required = true)]
+        git_analysis_file: String,
+
+        /// Only keep files under this path, relative to the workspace root.
+        /// Can be repeated.
+        #[arg(long, required = true)]
+        path_prefix: Vec<String>,
     },
     /// Analyze Bazel dependency graph
     BazelDeps {
         /// Path to the workspace root
-        #[arg(long, required = true)]
-        workspace_root: String,
+        #[arg(long, required_unless_present = "bazel_query_output")]
+        workspace_root: Option<String>,
 
-        /// The target to analyze
-        #[arg(long, required = true)]
-        target: String,
+        /// The target to analyze. Repeatable, to build a single graph covering
+        /// all of them
+        #[arg(long, required_unless_present = "bazel_query_output")]
+        target: Vec<String>,
+
+        /// Path to saved `bazel query --output streamed_jsonproto` output to
+        /// use instead of running bazel. May be gzipped, or `-` for stdin
+        #[arg(long)]
+        bazel_query_output: Option<String>,
+
+        /// Fail if any line of the bazel query output cannot be parsed
+        #[arg(long)]
+        strict: bool,
+
+        /// Keep each rule's deps and source files in bazel's ruleInput order
+        /// instead of sorting them. The order varies across bazel versions,
+        /// so the output is not stable
+        #[arg(long)]
+        preserve_input_order: bool,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum QueryCommands {
+    /// List the dependencies of a target
+    Deps {
+        /// The target, or a pattern such as //foo/... to list the
+        /// dependencies of every target it matches
+        label: String,
+
+        /// Include indirect dependencies
+        #[arg(long)]
+        transitive: bool,
+    },
+    /// List the targets depending on a target
+    Rdeps {
+        /// The target, or a pattern such as //foo/... to list the targets
+        /// depending on any target it matches
+        label: String,
+
+        /// Include indirect dependents
+        #[arg(long)]
+        transitive: bool,
+    },
+    /// List the targets with a source file in their srcs
+    Owner {
+        /// The source file, relative to the workspace root
+        path: String,
     },
 }
 
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq)]
+enum SortBy {
+    Score,
+    RebuildsPerKloc,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq)]
+enum OnlyVisibility {
+    /// only report targets visible outside their own package
+    Public,
+    Any,
+}
+
 #[derive(clap::Subcommand)]
 enum AnalyzeCommands {
     /// Generate trigger scores map
-    TriggerScoresMap {},
+    TriggerScoresMap {
+        /// Count the lines of code owned by each target, to report
+        /// rebuilds_per_kloc
+        #[arg(long)]
+        with_loc: bool,
+
+        /// The field to sort results by. Sorting by rebuilds_per_kloc
+        /// implies --with-loc. With --cost-file, score sorts by
+        /// weighted_score
+        #[arg(long, value_enum, default_value_t = SortBy::Score)]
+        sort_by: SortBy,
+
+        /// A JSON object or CSV file of target label to build cost, to
+        /// report cost and weighted_score (score × cost). May be gzipped, or
+        /// `-` for stdin
+        #[arg(long)]
+        cost_file: Option<String>,
+
+        /// The cost of targets missing from the cost file. Without it, they
+        /// have no weighted_score
+        #[arg(long, requires = "cost_file")]
+        default_cost: Option<f64>,
+    },
+    /// Generate trigger scores treating each directory as a target, for
+    /// repositories that don't use bazel. Does not require --target.
+    DirScores {
+        /// The maximum directory depth to treat as a target. Files in deeper
+        /// directories count towards their ancestor at this depth.
+        #[arg(long, default_value_t = 2)]
+        depth: usize,
+
+        /// Count the lines of code owned by each directory, to report
+        /// rebuilds_per_kloc
+        #[arg(long)]
+        with_loc: bool,
+
+        /// The field to sort results by. Sorting by rebuilds_per_kloc
+        /// implies --with-loc
+        #[arg(long, value_enum, default_value_t = SortBy::Score)]
+        sort_by: SortBy,
+    },
+    /// Attribute trigger scores to individual source files, to find the
+    /// files worth splitting out of their targets
+    FileTriggerScores,
+    /// Print the dependency graph in Graphviz DOT format, with each target
+    /// colored by its trigger score. Ignores --format
+    Graph {
+        /// Only include the N highest scoring targets, and the edges between
+        /// them
+        #[arg(long)]
+        max_nodes: Option<usize>,
+    },
+    /// List the targets rebuilt by specific commits
+    DependentsOfChange {
+        /// The commit to look up. Can be repeated
+        #[arg(long, required = true)]
+        commit: Vec<String>,
+
+        /// Include the full list of triggered targets for each commit
+        #[arg(long)]
+        list_targets: bool,
+    },
     /// Find most unique triggers
-    MostUniqueTriggers {},
+    MostUniqueTriggers {
+        /// Path to write a removal suggestions document to, which can be
+        /// verified with `removable-deps --from-suggestions`
+        #[arg(long)]
+        suggestions_output: Option<String>,
+
+        /// How many edges away from the target to look for dependencies to
+        /// remove
+        #[arg(long, default_value_t = 1)]
+        depth: usize,
+    },
     /// Analyze removable dependencies
     RemovableDeps {
         /// Test targets to verify against
-        #[arg(long, required = true)]
+        #[arg(long, required_unless_present = "from_suggestions")]
         test: Vec<String>,
+
+        /// Path to a suggestions document produced by `most-unique-triggers`.
+        /// Only the top suggestions by score are verified.
+        #[arg(long)]
+        from_suggestions: Option<String>,
+
+        /// The number of suggestions to verify when using --from-suggestions.
+        /// Unlike --top, this limits the builds run rather than the rows
+        /// written
+        #[arg(long, default_value_t = 10, requires = "from_suggestions")]
+        verify_top: usize,
     },
 }
 
 fn main() {
-    main_inner().unwrap();
+    if let Err(e) = main_inner() {
+        eprintln!("error: {}", e);
+        std::process::exit(e.exit_code());
+    }
 }
 
-fn main_inner() -> anyhow::Result<(), Box<dyn Error>> {
-    setup()?;
+fn main_inner() -> Result<(), DepsawError> {
+    setup();
     let args = Args::parse();
     info!("Starting analysis");
 
-    match args.command {
+    let rngs = rng::RngFactory::new(args.seed);
+    let update_check = update_check::passive_check(args.update_check);
+    let result = run(args.command, &rngs);
+    if let Some(update_check) = update_check {
+        // the check is bounded by curl's timeout, and overlapped with the run.
+        let _ = update_check.join();
+    }
+    if args.resource_summary {
+        info!("resource usage: {}", metrics::ResourceSummary::collect());
+    }
+    if let Some(path) = &args.metadata_output {
+        let metadata = metadata::RunMetadata::collect(
+            std::env::args().skip(1).collect(),
+            rngs.seed(),
+            &result,
+        );
+        let written = metadata.to_file(path);
+        // the run's own error is the one worth reporting.
+        return result.and(written);
+    }
+    result
+}
+
+fn run(command: Commands, rngs: &rng::RngFactory) -> Result<(), DepsawError> {
+    match command {
         Commands::Precalculate { output, command } => match command {
             PrecalculateCommands::GitRepo {
                 workspace_root,
                 since,
+                no_renames,
+                path_prefix,
+                exclude_path,
+                update,
+            } => {
+                let options = git::HistoryOptions {
+                    since,
+                    detect_renames: !no_renames,
+                    path_prefixes: path_prefix,
+                    exclude_paths: exclude_path,
+                };
+                let repo = if update {
+                    match git::GitRepo::from_file(&output) {
+                        Ok(existing) => existing.update(&workspace_root, &options)?,
+                        Err(e) => {
+                            warn!(
+                                "unable to read {} for update, rebuilding from scratch: {}",
+                                output, e
+                            );
+                            git::GitRepo::from_path(&workspace_root, &options)?
+                        }
+                    }
+                } else {
+                    git::GitRepo::from_path(&workspace_root, &options)?
+                };
+                repo.to_file(&output)
+            }
+            PrecalculateCommands::FilterGitRepo {
+                git_analysis_file,
+                path_prefix,
             } => {
-                let repo = git::GitRepo::from_path(&workspace_root, since).unwrap();
-                let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&repo)?;
-                let mut file = File::create(output).unwrap();
-                file.write_all(&bytes).unwrap();
-                Ok(())
+                let repo = git::GitRepo::from_file(&git_analysis_file)?.filter_paths(&path_prefix);
+                repo.to_file(&output)
             }
             PrecalculateCommands::BazelDeps {
                 workspace_root,
                 target,
+                bazel_query_output,
+                strict,
+                preserve_input_order,
             } => {
-                let deps_graph =
-                    bazel::BazelDependencyGraph::from_workspace(&workspace_root, &target)?;
-                let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&deps_graph)?;
-                let mut file = File::create(output).unwrap();
-                file.write_all(&bytes).unwrap();
-                Ok(())
+                let options = bazel::ParseOptions {
+                    strict,
+                    preserve_input_order,
+                };
+                let deps_graph = match (bazel_query_output, workspace_root) {
+                    (Some(path), _) => {
+                        bazel::BazelDependencyGraph::from_query_output(&path, options)?
+                    }
+                    (None, Some(workspace_root)) => bazel::BazelDependencyGraph::from_workspace(
+                        &workspace_root,
+                        &target,
+                        options,
+                    )?,
+                    _ => unreachable!(
+                        "clap requires --workspace-root and --target without --bazel-query-output"
+                    ),
+                };
+                deps_graph.to_file(&output)
             }
         },
+        Commands::CheckUpdate => update_check::check_update(),
+        Commands::Query {
+            bazel_analysis_file,
+            output,
+            operation,
+        } => {
+            let deps_graph = bazel::BazelDependencyGraph::from_file(&bazel_analysis_file)?;
+            let results = match operation {
+                QueryCommands::Deps { label, transitive } => deps_graph.deps(&label, transitive)?,
+                QueryCommands::Rdeps { label, transitive } => {
+                    deps_graph.rdeps(&label, transitive)?
+                }
+                QueryCommands::Owner { path } => {
+                    let owners = deps_graph.owners(&path);
+                    if owners.is_empty() {
+                        warn!("no target in the graph lists {} as a source file", path);
+                    }
+                    owners
+                }
+            };
+            output.write(&results)
+        }
+        Commands::Stats {
+            bazel_analysis_file,
+            output,
+        } => {
+            let deps_graph = bazel::BazelDependencyGraph::from_file(&bazel_analysis_file)?;
+            output.write(&deps_graph.external_repo_summary())
+        }
+        Commands::ScoreDiff {
+            baseline,
+            current,
+            baseline_metadata,
+            current_metadata,
+            align_window,
+            strict_align,
+            window_tolerance_days,
+            output,
+        } => {
+            let window_days = if align_window {
+                let read_window = |path: Option<String>| match path {
+                    Some(path) => metadata::read_window(&path),
+                    None => Ok(None),
+                };
+                algorithms::score_diff::align_windows(
+                    read_window(baseline_metadata)?,
+                    read_window(current_metadata)?,
+                    window_tolerance_days,
+                    strict_align,
+                )?
+            } else {
+                None
+            };
+            let deltas = algorithms::score_diff(
+                &algorithms::score_diff::read_scores(&baseline)?,
+                &algorithms::score_diff::read_scores(&current)?,
+                window_days,
+            );
+            output.write(&deltas)
+        }
         Commands::Analyze {
             workspace_root,
             target,
             since,
             git_analysis_file,
             bazel_analysis_file,
-            format,
+            bazel_query_output,
+            strict,
+            strict_io,
+            output,
+            determinism_check,
+            only_visibility,
             algorithm,
         } => {
             let workspace_root = workspace_root.unwrap_or_else(|| ".".to_string());
 
-            // Load dependencies
-            let deps_graph = if let Some(deps_file) = bazel_analysis_file {
-                bazel::BazelDependencyGraph::from_file(&deps_file)?
-            } else {
-                bazel::BazelDependencyGraph::from_workspace(&workspace_root, &target)?
-            };
-
             // Load git repo
             let repo = if let Some(git_analysis_file) = git_analysis_file {
-                git::GitRepo::from_file(&git_analysis_file).unwrap()
+                git::GitRepo::from_file(&git_analysis_file)?
             } else {
-                git::GitRepo::from_path(&workspace_root, since).unwrap()
+                let options = git::HistoryOptions {
+                    since,
+                    ..Default::default()
+                };
+                git::GitRepo::from_path(&workspace_root, &options)?
+            };
+            if repo.is_empty() {
+                warn!("git analysis has no commits in the requested range, all trigger scores will be zero");
+            }
+            if let Some(window) = repo.window {
+                metadata::record_window(window);
+            }
+
+            let parse_options = bazel::ParseOptions {
+                strict,
+                ..Default::default()
+            };
+
+            // Load dependencies. Directory scores synthesize a graph from
+            // the git history instead of querying bazel.
+            let (targets, deps_graph) = match &algorithm {
+                AnalyzeCommands::DirScores { depth, .. } => (
+                    vec!["//...".to_string()],
+                    directories::graph_from_directories(&repo, *depth),
+                ),
+                _ => {
+                    if target.is_empty() {
+                        return Err(DepsawError::InvalidInput(
+                            "--target is required for this algorithm".to_string(),
+                        ));
+                    }
+                    let mut targets = target;
+                    targets.sort();
+                    targets.dedup();
+                    let deps_graph = if let Some(deps_file) = bazel_analysis_file {
+                        bazel::BazelDependencyGraph::from_file(&deps_file)?
+                    } else if let Some(query_output) = &bazel_query_output {
+                        bazel::BazelDependencyGraph::from_query_output(query_output, parse_options)?
+                    } else {
+                        bazel::BazelDependencyGraph::from_workspace(
+                            &workspace_root,
+                            &targets,
+                            parse_options,
+                        )?
+                    };
+                    (targets, deps_graph)
+                }
+            };
+
+            // read once, outside of the determinism check's runs.
+            let (costs, default_cost) = match &algorithm {
+                AnalyzeCommands::TriggerScoresMap {
+                    cost_file: Some(cost_file),
+                    default_cost,
+                    ..
+                } => {
+                    if default_cost.is_some_and(|cost| !cost.is_finite() || cost < 0.0) {
+                        return Err(DepsawError::InvalidInput(
+                            "--default-cost must be a non-negative number".to_string(),
+                        ));
+                    }
+                    (Some(cost::read_costs(cost_file)?), *default_cost)
+                }
+                _ => (None, None),
             };
 
             match algorithm {
-                AnalyzeCommands::TriggerScoresMap {} => {
-                    let scores_by_target =
-                        algorithms::calculate_trigger_scores(&target, &repo, &deps_graph)?;
-                    let mut sorted_scores: Vec<_> = scores_by_target.iter().collect();
-                    sorted_scores.sort_by(|a, b| b.1.cmp(a.1));
-                    serialize!(sorted_scores, format);
+                AnalyzeCommands::TriggerScoresMap {
+                    with_loc, sort_by, ..
+                }
+                | AnalyzeCommands::DirScores {
+                    with_loc, sort_by, ..
+                } => {
+                    let sorted_scores = run_checked(
+                        determinism_check,
+                        &targets,
+                        rngs,
+                        |targets| {
+                            let mut scores_by_target = visible_trigger_scores(
+                                targets,
+                                &repo,
+                                &deps_graph,
+                                only_visibility,
+                            )?;
+                            if with_loc || sort_by == SortBy::RebuildsPerKloc {
+                                let mut workspace =
+                                    workspace::Workspace::new(&workspace_root, strict_io);
+                                let stats = algorithms::trigger_scores::add_lines_of_code(
+                                    &mut scores_by_target,
+                                    &mut workspace,
+                                    &deps_graph,
+                                )?;
+                                metadata::record_loc_stats(&stats);
+                                if !stats.skipped_paths.is_empty() {
+                                    warn!(
+                                    "{} source files could not be read and were counted as zero lines, see --metadata-output",
+                                    stats.skipped_paths.len()
+                                );
+                                }
+                                if stats.files_missing > 0 {
+                                    warn!(
+                                    "{} source files were not found under {}, and counted as zero lines",
+                                    stats.files_missing, workspace_root
+                                );
+                                }
+                            }
+                            if let Some(costs) = &costs {
+                                algorithms::trigger_scores::add_costs(
+                                    &mut scores_by_target,
+                                    costs,
+                                    default_cost,
+                                    &deps_graph,
+                                );
+                            }
+                            Ok(sort_trigger_scores(scores_by_target, sort_by))
+                        },
+                    )?;
+                    output.write(&sorted_scores)?;
+                    Ok(())
+                }
+                AnalyzeCommands::FileTriggerScores => {
+                    let results = run_checked(determinism_check, &targets, rngs, |targets| {
+                        let mut results =
+                            algorithms::file_trigger_scores(targets, &repo, &deps_graph)?;
+                        if only_visibility == OnlyVisibility::Public {
+                            results.retain(|r| deps_graph.is_public(&r.target));
+                        }
+                        Ok(results)
+                    })?;
+                    output.write(&results)?;
                     Ok(())
                 }
-                AnalyzeCommands::MostUniqueTriggers {} => {
-                    let results = algorithms::most_unique_triggers(&target, &repo, &deps_graph)?;
-                    serialize!(results, format);
+                AnalyzeCommands::Graph { max_nodes } => {
+                    let dot = run_checked(determinism_check, &targets, rngs, |targets| {
+                        let scores_by_target =
+                            visible_trigger_scores(targets, &repo, &deps_graph, only_visibility)?;
+                        Ok(dot::render(&scores_by_target, &deps_graph, max_nodes))
+                    })?;
+                    print!("{}", dot);
+                    Ok(())
+                }
+                AnalyzeCommands::DependentsOfChange {
+                    commit,
+                    list_targets,
+                } => {
+                    let results = run_checked(determinism_check, &targets, rngs, |_| {
+                        algorithms::dependents_of_change(&commit, &repo, &deps_graph, list_targets)
+                    })?;
+                    output.write(&results)?;
+                    Ok(())
+                }
+                AnalyzeCommands::MostUniqueTriggers {
+                    suggestions_output,
+                    depth,
+                } => {
+                    let target = single_target(&targets, "most-unique-triggers")?;
+                    let results = run_checked(determinism_check, &targets, rngs, |_| {
+                        algorithms::most_unique_triggers(target, &repo, &deps_graph, depth)
+                    })?;
+                    if let Some(path) = suggestions_output {
+                        let suggestions =
+                            algorithms::removal_suggestions(target, &results, &deps_graph)?;
+                        info!("writing removal suggestions to {}", path);
+                        suggestions.to_file(&path)?;
+                    }
+                    output.write(&results)?;
                     Ok(())
                 }
                 // TODO: move this to it's own operations subcommands
-                AnalyzeCommands::RemovableDeps { test } => {
+                AnalyzeCommands::RemovableDeps {
+                    test,
+                    from_suggestions,
+                    verify_top,
+                } => {
+                    let target = single_target(&targets, "removable-deps")?;
+                    if determinism_check {
+                        return Err(DepsawError::InvalidInput(
+                            "--determinism-check is not supported by removable-deps, which runs builds"
+                                .to_string(),
+                        ));
+                    }
                     info!("Analyzing target: {}", target);
                     info!("Test targets:");
                     for test_target in &test {
                         info!("  {}", test_target);
                     }
 
-                    // Get deps for the target
-                    let deps = operations::get_deps(&target);
-                    let mut removable_deps = Vec::new();
+                    // Get the deps to try, along with the target to remove each
+                    // from and the tests that verify it
+                    let runner = operations::SystemRunner;
+                    let candidates = match from_suggestions {
+                        Some(path) => {
+                            let suggestions = algorithms::Suggestions::from_file(&path)?;
+                            if suggestions.target != target {
+                                return Err(DepsawError::InvalidInput(format!(
+                                    "suggestions in {} are for target {}, not {}",
+                                    path, suggestions.target, target
+                                )));
+                            }
+                            operations::candidates_from_suggestions(
+                                &runner,
+                                &suggestions,
+                                verify_top,
+                                &test,
+                            )?
+                        }
+                        None => operations::get_deps(&runner, target)?
+                            .into_iter()
+                            .map(|dep| operations::Candidate {
+                                from_target: target.to_string(),
+                                dep,
+                                tests: test.clone(),
+                            })
+                            .collect(),
+                    };
 
                     // Try removing each dep
-                    for dep in deps {
-                        if operations::test_passes_without_dep(&target, &dep, &test) {
-                            removable_deps.push(dep);
-                        }
-                    }
+                    let results = operations::verify_removals(&runner, &candidates)?;
 
-                    // Print results
+                    // Summarize results
+                    let removable_deps: Vec<_> = results.iter().filter(|r| r.removable).collect();
                     if removable_deps.is_empty() {
-                        println!("\nNo removable dependencies found.");
+                        info!("No removable dependencies found.");
                     } else {
-                        println!("\nThe following dependencies can potentially be removed:");
-                        for dep in removable_deps {
-                            println!("  {}", dep);
+                        info!("The following dependencies can potentially be removed:");
+                        for result in removable_deps {
+                            info!("  {}", result.dep);
                         }
                     }
+                    output.write(&results)?;
                     Ok(())
                 }
             }
@@ -236,7 +815,93 @@ fn main_inner() -> anyhow::Result<(), Box<dyn Error>> {
     }
 }
 
-fn setup() -> Result<(), Box<dyn Error>> {
+/// the target of an algorithm that analyzes exactly one.
+fn single_target<'a>(targets: &'a [String], algorithm: &str) -> Result<&'a str, DepsawError> {
+    match targets {
+        [target] => Ok(target),
+        _ => Err(DepsawError::InvalidInput(format!(
+            "{} analyzes a single target, but {} were given",
+            algorithm,
+            targets.len()
+        ))),
+    }
+}
+
+/// run an analysis of the targets. With --determinism-check, the analysis is
+/// run a second time with the targets shuffled, and fails unless both results
+/// serialize identically.
+fn run_checked<T: serde::Serialize>(
+    determinism_check: bool,
+    targets: &[String],
+    rngs: &rng::RngFactory,
+    analysis: impl Fn(&[String]) -> Result<T, DepsawError>,
+) -> Result<T, DepsawError> {
+    let result = analysis(targets)?;
+    if !determinism_check {
+        return Ok(result);
+    }
+    let mut shuffled = targets.to_vec();
+    rngs.rng("determinism-check").shuffle(&mut shuffled);
+    let first = serde_json::to_string_pretty(&result)?;
+    let second = serde_json::to_string_pretty(&analysis(&shuffled)?)?;
+    if first != second {
+        let line = first
+            .lines()
+            .zip(second.lines())
+            .position(|(a, b)| a != b)
+            .unwrap_or_else(|| first.lines().count().min(second.lines().count()));
+        return Err(DepsawError::DeterminismCheckFailed(format!(
+            "the serialized results of the two runs differ from line {}",
+            line + 1
+        )));
+    }
+    info!("determinism check passed");
+    Ok(result)
+}
+
+/// the trigger scores of the targets, leaving out the targets that aren't
+/// public with `--only-visibility public`. The filtered targets are still in
+/// the graph, so their commits still trigger the targets depending on them.
+fn visible_trigger_scores(
+    targets: &[String],
+    repo: &git::GitRepo,
+    deps_graph: &bazel::BazelDependencyGraph,
+    only_visibility: OnlyVisibility,
+) -> Result<HashMap<String, ResolvedTarget>, DepsawError> {
+    let mut scores_by_target = algorithms::calculate_trigger_scores(targets, repo, deps_graph)?;
+    if only_visibility == OnlyVisibility::Public {
+        scores_by_target.retain(|name, _| deps_graph.is_public(name));
+    }
+    Ok(scores_by_target)
+}
+
+/// sort the scores, breaking ties by name so the order is stable.
+fn sort_trigger_scores(
+    scores_by_target: HashMap<String, ResolvedTarget>,
+    sort_by: SortBy,
+) -> Vec<(String, ResolvedTarget)> {
+    let mut sorted_scores: Vec<_> = scores_by_target.into_iter().collect();
+    match sort_by {
+        // weighted_score is only set when costs were given, and unweighted
+        // targets sort after weighted ones.
+        SortBy::Score => sorted_scores.sort_by(|a, b| {
+            b.1.weighted_score
+                .unwrap_or(-1.0)
+                .total_cmp(&a.1.weighted_score.unwrap_or(-1.0))
+                .then_with(|| b.1.score.cmp(&a.1.score))
+                .then_with(|| a.0.cmp(&b.0))
+        }),
+        SortBy::RebuildsPerKloc => sorted_scores.sort_by(|a, b| {
+            b.1.rebuilds_per_kloc
+                .unwrap_or(0.0)
+                .total_cmp(&a.1.rebuilds_per_kloc.unwrap_or(0.0))
+                .then_with(|| a.0.cmp(&b.0))
+        }),
+    }
+    sorted_scores
+}
+
+fn setup() {
     let filter = tracing_subscriber::EnvFilter::builder()
         .with_default_directive(LevelFilter::INFO.into())
         .from_env_lossy();
@@ -244,6 +909,134 @@ fn setup() -> Result<(), Box<dyn Error>> {
         .with_writer(std::io::stderr)
         .with_env_filter(filter)
         .finish();
-    tracing::subscriber::set_global_default(subscriber)?;
-    Ok(())
+    // this only fails if a subscriber was already set.
+    tracing::subscriber::set_global_default(subscriber).expect("failed to set up logging");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn arguments_are_consistent() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn verify_top_is_separate_from_top() {
+        let args = Args::try_parse_from([
+            "depsaw",
+            "analyze",
+            "--target",
+            "//app:bin",
+            "--top",
+            "5",
+            "removable-deps",
+            "--from-suggestions",
+            "suggestions.yaml",
+            "--verify-top",
+            "3",
+        ])
+        .unwrap();
+        let Commands::Analyze {
+            output, algorithm, ..
+        } = args.command
+        else {
+            panic!("expected analyze");
+        };
+        assert_eq!(output.top, Some(5));
+        assert!(matches!(
+            algorithm,
+            AnalyzeCommands::RemovableDeps { verify_top: 3, .. }
+        ));
+    }
+
+    #[test]
+    fn determinism_check_catches_order_dependent_results() {
+        let targets: Vec<String> = (0..10).map(|i| format!("//t:{}", i)).collect();
+        let rngs = rng::RngFactory::new(Some(1));
+        let first = |targets: &[String]| Ok(targets[0].clone());
+        assert!(matches!(
+            run_checked(true, &targets, &rngs, first),
+            Err(DepsawError::DeterminismCheckFailed(_))
+        ));
+        assert_eq!(run_checked(false, &targets, &rngs, first).unwrap(), "//t:0");
+
+        let sorted = |targets: &[String]| {
+            let mut targets = targets.to_vec();
+            targets.sort();
+            Ok(targets)
+        };
+        assert_eq!(run_checked(true, &targets, &rngs, sorted).unwrap(), targets);
+    }
+
+    #[test]
+    fn private_targets_still_trigger_public_dependents() {
+        let deps_graph = testing::graph(&[
+            testing::rule("//app:bin", "java_binary", &["//app:impl"]),
+            testing::rule_with_attributes(
+                "//app:impl",
+                &["//app:Impl.java"],
+                &[],
+                &["//visibility:private"],
+            ),
+            testing::source("//app:Impl.java"),
+        ]);
+        let repo = testing::repo(&[("app/Impl.java", &["c1", "c2"])]);
+        let targets = vec!["//app:bin".to_string()];
+
+        let any =
+            visible_trigger_scores(&targets, &repo, &deps_graph, OnlyVisibility::Any).unwrap();
+        assert_eq!(any.len(), 2);
+        let public =
+            visible_trigger_scores(&targets, &repo, &deps_graph, OnlyVisibility::Public).unwrap();
+        assert_eq!(public.keys().collect::<Vec<_>>(), vec!["//app:bin"]);
+        assert_eq!(public["//app:bin"].rebuilds, 2);
+        assert_eq!(public["//app:bin"], any["//app:bin"]);
+    }
+
+    #[test]
+    fn weighted_targets_sort_first_then_by_score_and_name() {
+        let target = |name: &str, score: usize, weighted_score: Option<f64>| {
+            let target = ResolvedTarget {
+                name: name.to_string(),
+                rebuilds: score,
+                immediate_dependents: 0,
+                total_dependents: 0,
+                score,
+                total_lines: None,
+                rebuilds_per_kloc: None,
+                external_dep_count: 0,
+                cost: weighted_score.map(|w| w / score as f64),
+                weighted_score,
+                triggering_roots: vec![],
+                commits: Default::default(),
+            };
+            (name.to_string(), target)
+        };
+        let scores: HashMap<String, ResolvedTarget> = [
+            target("//unweighted:lib", 100, None),
+            target("//cheap:lib", 50, Some(5.0)),
+            target("//slow:lib", 2, Some(600.0)),
+            target("//b:lib", 10, None),
+            target("//a:lib", 10, None),
+        ]
+        .into_iter()
+        .collect();
+        let order: Vec<String> = sort_trigger_scores(scores, SortBy::Score)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                "//slow:lib",
+                "//cheap:lib",
+                "//unweighted:lib",
+                "//a:lib",
+                "//b:lib"
+            ]
+        );
+    }
 }
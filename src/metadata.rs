@@ -0,0 +1,151 @@
+//! The metadata envelope describing a run, written next to the results with
+//! `--metadata-output` so CI can track depsaw itself alongside what it
+//! reported.
+use super::error::{DepsawError, Result};
+use super::git::Window;
+use super::loc::LocStats;
+use super::metrics::ResourceSummary;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+static LOC_STATS: Mutex<Option<LocStats>> = Mutex::new(None);
+static WINDOW: Mutex<Option<Window>> = Mutex::new(None);
+
+/// record the stats of counting lines of code, including the paths that
+/// were skipped, to report in the envelope.
+pub fn record_loc_stats(stats: &LocStats) {
+    *LOC_STATS.lock().unwrap() = Some(stats.clone());
+}
+
+/// record the window of history analyzed, so that the results of runs can
+/// be compared with `score-diff --align-window`.
+pub fn record_window(window: Window) {
+    *WINDOW.lock().unwrap() = Some(window);
+}
+
+/// the window of history recorded in an envelope written by another run,
+/// if it analyzed any.
+pub fn read_window(path: &str) -> Result<Option<Window>> {
+    #[derive(Deserialize)]
+    struct Envelope {
+        #[serde(default)]
+        window: Option<Window>,
+    }
+    let content = std::fs::read_to_string(path).map_err(|e| {
+        DepsawError::IoError(std::io::Error::new(
+            e.kind(),
+            format!("unable to read {}: {}", path, e),
+        ))
+    })?;
+    Ok(serde_json::from_str::<Envelope>(&content)?.window)
+}
+
+#[derive(Debug, Serialize)]
+pub struct RunMetadata {
+    pub depsaw_version: String,
+    /// the arguments depsaw was run with, without the program name.
+    pub args: Vec<String>,
+    /// the seed of the run's random choices, to reproduce it with `--seed`.
+    pub seed: u64,
+    pub success: bool,
+    /// the error the run failed with, if it did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub resources: ResourceSummary,
+    /// how the lines of code were counted, when they were.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub loc: Option<LocStats>,
+    /// the window of history analyzed, when git history was read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window: Option<Window>,
+}
+
+impl RunMetadata {
+    /// collect the metadata of the run so far, which ended with `result`.
+    pub fn collect(args: Vec<String>, seed: u64, result: &Result<()>) -> RunMetadata {
+        RunMetadata {
+            depsaw_version: env!("CARGO_PKG_VERSION").to_string(),
+            args,
+            seed,
+            success: result.is_ok(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            resources: ResourceSummary::collect(),
+            loc: LOC_STATS.lock().unwrap().clone(),
+            window: *WINDOW.lock().unwrap(),
+        }
+    }
+
+    pub fn to_file(&self, path: &str) -> Result<()> {
+        let content = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, content + "\n").map_err(|e| {
+            DepsawError::IoError(std::io::Error::new(
+                e.kind(),
+                format!("unable to write {}: {}", path, e),
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics;
+    use crate::testing::TempDir;
+    use crate::workspace::SkippedPath;
+
+    #[test]
+    fn envelope_records_the_run() {
+        let dir = TempDir::new("metadata");
+        let path = dir.join("metadata.json");
+        metrics::record_subprocess();
+        let result = Err(DepsawError::InvalidInput("bad target".to_string()));
+        let args = vec!["analyze".to_string(), "--target".to_string()];
+        RunMetadata::collect(args, 42, &result)
+            .to_file(&path)
+            .unwrap();
+
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written["depsaw_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(written["args"], serde_json::json!(["analyze", "--target"]));
+        assert_eq!(written["seed"], 42);
+        assert_eq!(written["success"], false);
+        assert_eq!(written["error"], "bad target");
+        assert!(written["resources"]["subprocesses"].as_u64().unwrap() >= 1);
+        assert!(written.get("loc").is_none());
+        assert!(written.get("window").is_none());
+        assert_eq!(read_window(&path).unwrap(), None);
+
+        record_loc_stats(&LocStats {
+            files_counted: 3,
+            files_missing: 1,
+            skipped_paths: vec![SkippedPath {
+                path: "a/A.java".to_string(),
+                error_kind: "permission denied".to_string(),
+            }],
+            ..Default::default()
+        });
+        let window = Window {
+            start: 1_700_000_000,
+            end: 1_700_604_800,
+        };
+        record_window(window);
+        let metadata = RunMetadata::collect(vec![], 0, &Ok(()));
+        *LOC_STATS.lock().unwrap() = None;
+        *WINDOW.lock().unwrap() = None;
+        metadata.to_file(&path).unwrap();
+        assert_eq!(read_window(&path).unwrap(), Some(window));
+        let written = serde_json::to_value(&metadata).unwrap();
+        assert_eq!(written["success"], true);
+        assert!(written.get("error").is_none());
+        assert_eq!(written["loc"]["files_missing"], 1);
+        assert_eq!(
+            written["loc"]["skipped_paths"],
+            serde_json::json!([{"path": "a/A.java", "error_kind": "permission denied"}])
+        );
+        assert_eq!(
+            written["window"],
+            serde_json::json!({"start": 1_700_000_000, "end": 1_700_604_800})
+        );
+    }
+}
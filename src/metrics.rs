@@ -0,0 +1,170 @@
+//! Lightweight self-metrics, reported at the end of every run to help size
+//! CI runners.
+//!
+//! Counters are incremented at the subprocess and analysis file call sites.
+//! CPU time and peak memory come from `getrusage` on unix, and are not
+//! reported on other platforms.
+use serde::Serialize;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+struct Counters {
+    subprocesses: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+impl Counters {
+    const fn new() -> Counters {
+        Counters {
+            subprocesses: AtomicU64::new(0),
+            bytes_read: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+        }
+    }
+}
+
+static COUNTERS: Counters = Counters::new();
+
+pub fn record_subprocess() {
+    COUNTERS.subprocesses.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_bytes_read(bytes: usize) {
+    COUNTERS
+        .bytes_read
+        .fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+pub fn record_bytes_written(bytes: usize) {
+    COUNTERS
+        .bytes_written
+        .fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct ResourceSummary {
+    pub peak_rss_kb: Option<u64>,
+    pub user_cpu_secs: Option<f64>,
+    pub system_cpu_secs: Option<f64>,
+    pub subprocesses: u64,
+    /// bytes read from analysis files.
+    pub bytes_read: u64,
+    /// bytes written to analysis files.
+    pub bytes_written: u64,
+}
+
+impl ResourceSummary {
+    pub fn collect() -> ResourceSummary {
+        let (peak_rss_kb, user_cpu_secs, system_cpu_secs) = match rusage() {
+            Some((rss, user, system)) => (Some(rss), Some(user), Some(system)),
+            None => (None, None, None),
+        };
+        ResourceSummary {
+            peak_rss_kb,
+            user_cpu_secs,
+            system_cpu_secs,
+            subprocesses: COUNTERS.subprocesses.load(Ordering::Relaxed),
+            bytes_read: COUNTERS.bytes_read.load(Ordering::Relaxed),
+            bytes_written: COUNTERS.bytes_written.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl fmt::Display for ResourceSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn or_unknown<T: fmt::Display>(value: Option<T>) -> String {
+            value.map_or("unknown".to_string(), |v| v.to_string())
+        }
+        write!(
+            f,
+            "peak rss: {} KiB, user cpu: {}s, system cpu: {}s, subprocesses: {}, bytes read: {}, bytes written: {}",
+            or_unknown(self.peak_rss_kb),
+            or_unknown(self.user_cpu_secs.map(|s| format!("{:.2}", s))),
+            or_unknown(self.system_cpu_secs.map(|s| format!("{:.2}", s))),
+            self.subprocesses,
+            self.bytes_read,
+            self.bytes_written
+        )
+    }
+}
+
+/// returns the peak rss in KiB, and user and system cpu time in seconds.
+#[cfg(unix)]
+fn rusage() -> Option<(u64, f64, f64)> {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return None;
+    }
+    let to_secs = |t: libc::timeval| t.tv_sec as f64 + t.tv_usec as f64 / 1_000_000.0;
+    // ru_maxrss is reported in bytes on macOS, and KiB elsewhere.
+    let peak_rss_kb = if cfg!(target_os = "macos") {
+        usage.ru_maxrss as u64 / 1024
+    } else {
+        usage.ru_maxrss as u64
+    };
+    Some((
+        peak_rss_kb,
+        to_secs(usage.ru_utime),
+        to_secs(usage.ru_stime),
+    ))
+}
+
+#[cfg(not(unix))]
+fn rusage() -> Option<(u64, f64, f64)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::{GitRepo, HistoryOptions};
+    use crate::testing::{commit, git_repo, TempDir};
+
+    // the counters are shared by the whole process, including the other
+    // tests running in parallel, so only lower bounds can be checked.
+
+    #[test]
+    fn counts_subprocesses_and_analysis_file_io() {
+        let repo_dir = git_repo("metrics");
+        commit(&repo_dir, &[("a/A.java", "a")], "add a");
+        let out = TempDir::new("metrics-out");
+        let path = out.join("git.rkyv");
+        let before = ResourceSummary::collect();
+
+        // resolving HEAD, then reading the log.
+        let repo = GitRepo::from_path(repo_dir.path_str(), &HistoryOptions::default()).unwrap();
+        repo.to_file(&path).unwrap();
+        GitRepo::from_file(&path).unwrap();
+        GitRepo::from_file(&path).unwrap();
+
+        let after = ResourceSummary::collect();
+        let size = std::fs::metadata(&path).unwrap().len();
+        assert!(after.subprocesses - before.subprocesses >= 2);
+        assert!(after.bytes_written - before.bytes_written >= size);
+        assert!(after.bytes_read - before.bytes_read >= 2 * size);
+    }
+
+    #[test]
+    fn counts_from_every_thread() {
+        let before = ResourceSummary::collect();
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    for _ in 0..100 {
+                        record_subprocess();
+                        record_bytes_read(3);
+                        record_bytes_written(5);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let after = ResourceSummary::collect();
+        assert!(after.subprocesses - before.subprocesses >= 800);
+        assert!(after.bytes_read - before.bytes_read >= 2400);
+        assert!(after.bytes_written - before.bytes_written >= 4000);
+    }
+}
@@ -7,93 +7,636 @@
 //! * Test if a target's tests pass without a specific dependency
 //!
 //! All functions in this module expect the `buildozer` and `bazel` commands to be
-//! available in the system path.
+//! available in the system path. Commands are run through a `CommandRunner`,
+//! so tests can script their results instead.
+use super::algorithms::Suggestions;
+use super::error::{DepsawError, Result};
+use super::{argv, metrics, output};
+use serde::{Deserialize, Serialize};
 use std::process::Command;
+use std::time::Instant;
 use tracing::{error, info};
 
-pub fn get_deps(target: &str) -> Vec<String> {
-    let cmd_args = ["print deps", target];
-    info!("Executing: buildozer {}", cmd_args.join(" "));
+/// The output of a command that ran to completion.
+#[derive(Debug, Clone, Default)]
+pub struct CommandOutput {
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
 
-    let output = Command::new("buildozer")
-        .args(cmd_args)
-        .output()
-        .expect("Failed to execute buildozer");
+/// Runs the external commands operations are built on.
+pub trait CommandRunner {
+    /// run the command, erroring only if it could not be run at all.
+    fn run(&self, prog: &str, args: &[String]) -> Result<CommandOutput>;
 
-    if !output.status.success() {
-        error!(
-            "buildozer failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-        return Vec::new();
+    /// the largest command line to run, in bytes. Longer input is passed
+    /// through the tool's file based input instead.
+    fn argv_limit(&self) -> usize {
+        argv::MAX_ARGV_BYTES
     }
+}
 
-    String::from_utf8_lossy(&output.stdout)
-        .lines()
-        .map(|s| s.to_string())
-        .collect()
+/// Runs commands as subprocesses, from the current directory.
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, prog: &str, args: &[String]) -> Result<CommandOutput> {
+        metrics::record_subprocess();
+        let output = Command::new(prog).args(args).output().map_err(|e| {
+            DepsawError::CommandFailed(format!("unable to run {} {}: {}", prog, args.join(" "), e))
+        })?;
+        Ok(CommandOutput {
+            success: output.status.success(),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
 }
 
-pub fn remove_dep(target: &str, dep: &str) -> bool {
-    let cmd = format!("remove deps {}", dep);
-    info!("Executing: buildozer {} {}", cmd, target);
+/// The outcome of trying to remove a single dependency from a target.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RemovableDepResult {
+    pub dep: String,
+    /// true if the dep was removed and every test passed without it.
+    pub removable: bool,
+    /// written as a single `;` separated value, so CSV rows keep one column
+    /// per field.
+    #[serde(with = "output::delimited")]
+    pub failing_tests: Vec<String>,
+    /// wall time spent removing the dep and running the tests.
+    pub duration_secs: f64,
+    /// why the dep could not be removed to run the tests, if it couldn't.
+    #[serde(default)]
+    pub error: Option<String>,
+}
 
-    let output = Command::new("buildozer")
-        .args([&cmd, target])
-        .output()
-        .expect("Failed to execute buildozer");
+pub fn get_deps(runner: &dyn CommandRunner, target: &str) -> Result<Vec<String>> {
+    let output = buildozer(runner, "print deps", target)?;
+    // buildozer prints the list as `[//a:b //c:d]`.
+    Ok(output
+        .stdout
+        .split(|c: char| c.is_whitespace() || c == '[' || c == ']')
+        .filter(|dep| !dep.is_empty())
+        .map(|dep| dep.to_string())
+        .collect())
+}
 
-    if !output.status.success() {
-        error!(
-            "buildozer failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-        return false;
+/// the arguments running a buildozer command against a target. Commands too
+/// long to pass directly are passed in a `-f` command file, whose lines are
+/// `command|target`.
+fn buildozer_invocation(command: &str, target: &str, limit: usize) -> Result<argv::Invocation> {
+    Ok(argv::args_or_file(
+        "buildozer",
+        vec![command.to_string(), target.to_string()],
+        limit,
+        "buildozer-commands.txt",
+        || format!("{}|{}\n", command, target),
+        |path| vec!["-f".to_string(), path.display().to_string()],
+    )?)
+}
+
+/// run a buildozer command against a target, erroring if it fails.
+fn buildozer(runner: &dyn CommandRunner, command: &str, target: &str) -> Result<CommandOutput> {
+    // the command file, if any, is removed after buildozer has run.
+    let argv::Invocation {
+        args,
+        input_file: _command_file,
+    } = buildozer_invocation(command, target, runner.argv_limit())?;
+    info!("Executing: buildozer {}", args.join(" "));
+    let output = runner.run("buildozer", &args)?;
+    if !output.success {
+        return Err(DepsawError::CommandFailed(format!(
+            "buildozer '{}' {}: {}",
+            command,
+            target,
+            output.stderr.trim()
+        )));
     }
+    Ok(output)
+}
 
-    true
+pub fn remove_dep(runner: &dyn CommandRunner, target: &str, dep: &str) -> Result<()> {
+    buildozer(runner, &format!("remove deps {}", dep), target)?;
+    Ok(())
 }
 
-pub fn add_dep(target: &str, dep: &str) -> bool {
-    let cmd = format!("add deps {}", dep);
-    info!("Executing: buildozer {} {}", cmd, target);
+pub fn add_dep(runner: &dyn CommandRunner, target: &str, dep: &str) -> Result<()> {
+    buildozer(runner, &format!("add deps {}", dep), target)?;
+    Ok(())
+}
 
-    let output = Command::new("buildozer")
-        .args([&cmd, target])
-        .output()
-        .expect("Failed to execute buildozer");
+pub fn try_remove_dep(
+    runner: &dyn CommandRunner,
+    target: &str,
+    dep: &str,
+    test_targets: &[String],
+) -> Result<RemovableDepResult> {
+    let start = Instant::now();
+    if let Err(e) = remove_dep(runner, target, dep) {
+        // the tests would run against the unchanged target, so passing
+        // tests wouldn't show anything.
+        error!("unable to remove {} from {}: {}", dep, target, e);
+        return Ok(RemovableDepResult {
+            dep: dep.to_string(),
+            removable: false,
+            failing_tests: vec![],
+            duration_secs: start.elapsed().as_secs_f64(),
+            error: Some(e.to_string()),
+        });
+    }
+    let tested = run_tests(runner, test_targets);
+    // re-add the dep even if the tests couldn't run, so the BUILD file is
+    // left as it was.
+    add_dep(runner, target, dep).map_err(|e| {
+        DepsawError::CommandFailed(format!(
+            "unable to add {} back to {}, which is left without it: {}",
+            dep, target, e
+        ))
+    })?;
+    let failing_tests = tested?;
+    Ok(RemovableDepResult {
+        dep: dep.to_string(),
+        removable: failing_tests.is_empty(),
+        failing_tests,
+        duration_secs: start.elapsed().as_secs_f64(),
+        error: None,
+    })
+}
 
-    if !output.status.success() {
-        error!(
-            "buildozer failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-        return false;
+/// the test rules depending on the target anywhere in the workspace, found
+/// with a bazel rdeps query.
+pub fn find_tests(runner: &dyn CommandRunner, target: &str) -> Result<Vec<String>> {
+    let args = vec![
+        "query".to_string(),
+        format!("kind('.*_test rule', rdeps(//..., {}))", target),
+        "--output".to_string(),
+        "label".to_string(),
+    ];
+    info!("executing: bazel {}", args.join(" "));
+    let output = runner.run("bazel", &args)?;
+    if !output.success {
+        return Err(DepsawError::BazelQueryFailed(format!(
+            "bazel {}: {}",
+            args.join(" "),
+            output.stderr.trim()
+        )));
     }
+    Ok(output.stdout.lines().map(|l| l.to_string()).collect())
+}
 
-    true
+/// A dependency to try removing, and the tests verifying its removal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub from_target: String,
+    pub dep: String,
+    pub tests: Vec<String>,
 }
 
-pub fn test_passes_without_dep(target: &str, dep: &str, test_targets: &Vec<String>) -> bool {
-    remove_dep(target, dep);
-    let mut success = true;
+/// the candidates from the top suggestions by score. Each is verified with
+/// the given tests if any, otherwise with the suggestion's verify_tests, and
+/// otherwise with the tests bazel finds depending on its from_target.
+pub fn candidates_from_suggestions(
+    runner: &dyn CommandRunner,
+    suggestions: &Suggestions,
+    top: usize,
+    tests: &[String],
+) -> Result<Vec<Candidate>> {
+    let mut candidates = vec![];
+    for suggestion in suggestions.top(top) {
+        let tests = if !tests.is_empty() {
+            tests.to_vec()
+        } else if !suggestion.verify_tests.is_empty() {
+            suggestion.verify_tests.clone()
+        } else {
+            find_tests(runner, &suggestion.from_target)?
+        };
+        candidates.push(Candidate {
+            from_target: suggestion.from_target.clone(),
+            dep: suggestion.dep.clone(),
+            tests,
+        });
+    }
+    Ok(candidates)
+}
+
+/// try removing each candidate, erroring before building anything if one
+/// has no tests to verify it.
+pub fn verify_removals(
+    runner: &dyn CommandRunner,
+    candidates: &[Candidate],
+) -> Result<Vec<RemovableDepResult>> {
+    if let Some(candidate) = candidates.iter().find(|c| c.tests.is_empty()) {
+        return Err(DepsawError::InvalidInput(format!(
+            "no tests found to verify removal of {} from {}, pass them with --test",
+            candidate.dep, candidate.from_target
+        )));
+    }
+    candidates
+        .iter()
+        .map(|c| try_remove_dep(runner, &c.from_target, &c.dep, &c.tests))
+        .collect()
+}
+
+/// run each test, returning the ones that failed.
+fn run_tests(runner: &dyn CommandRunner, test_targets: &[String]) -> Result<Vec<String>> {
+    let mut failing_tests = Vec::new();
     for test in test_targets {
         info!("executing: bazel test {}", test);
+        let output = runner.run("bazel", &["test".to_string(), test.clone()])?;
+        if !output.success {
+            failing_tests.push(test.clone());
+            error!("bazel test failed: {}", output.stderr);
+        }
+    }
+    Ok(failing_tests)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    type Script = Box<dyn Fn(&str) -> Option<CommandOutput>>;
+
+    /// A runner answering each command line, as `prog arg...`, from a
+    /// script, and recording the command lines it was given. Commands the
+    /// script doesn't answer fail to run.
+    pub struct FakeRunner {
+        pub calls: RefCell<Vec<String>>,
+        script: Script,
+        argv_limit: usize,
+    }
+
+    impl FakeRunner {
+        pub fn new(script: impl Fn(&str) -> Option<CommandOutput> + 'static) -> FakeRunner {
+            FakeRunner {
+                calls: RefCell::new(vec![]),
+                script: Box::new(script),
+                argv_limit: argv::MAX_ARGV_BYTES,
+            }
+        }
+
+        /// a runner passing commands longer than `limit` through files.
+        pub fn with_argv_limit(mut self, limit: usize) -> FakeRunner {
+            self.argv_limit = limit;
+            self
+        }
+    }
+
+    impl CommandRunner for FakeRunner {
+        fn run(&self, prog: &str, args: &[String]) -> Result<CommandOutput> {
+            let command = format!("{} {}", prog, args.join(" "));
+            self.calls.borrow_mut().push(command.clone());
+            (self.script)(&command)
+                .ok_or_else(|| DepsawError::CommandFailed(format!("unable to run {}", command)))
+        }
+
+        fn argv_limit(&self) -> usize {
+            self.argv_limit
+        }
+    }
+
+    pub fn succeeded(stdout: &str) -> Option<CommandOutput> {
+        Some(CommandOutput {
+            success: true,
+            stdout: stdout.to_string(),
+            stderr: String::new(),
+        })
+    }
 
-        let output = Command::new("bazel")
-            .args(["test", test])
-            .output()
-            .expect("Failed to execute bazel");
+    pub fn failed(stderr: &str) -> Option<CommandOutput> {
+        Some(CommandOutput {
+            success: false,
+            stdout: String::new(),
+            stderr: stderr.to_string(),
+        })
+    }
+
+    #[test]
+    fn system_runner_errors_on_missing_program() {
+        let err = SystemRunner
+            .run("depsaw-no-such-command", &["print deps".to_string()])
+            .unwrap_err();
+        assert!(matches!(err, DepsawError::CommandFailed(_)));
+        assert!(err.to_string().contains("depsaw-no-such-command"));
+    }
+
+    #[test]
+    fn get_deps_parses_buildozer_list() {
+        let runner = FakeRunner::new(|_| succeeded("[//a:lib //b:lib]\n"));
+        assert_eq!(
+            get_deps(&runner, "//x:bin").unwrap(),
+            vec!["//a:lib", "//b:lib"]
+        );
+        assert_eq!(*runner.calls.borrow(), vec!["buildozer print deps //x:bin"]);
+    }
+
+    #[test]
+    fn get_deps_errors_when_buildozer_fails() {
+        let runner = FakeRunner::new(|_| failed("no such rule"));
+        let err = get_deps(&runner, "//x:bin").unwrap_err();
+        assert!(matches!(err, DepsawError::CommandFailed(_)));
+        assert!(err.to_string().contains("buildozer 'print deps' //x:bin"));
+        assert!(err.to_string().contains("no such rule"));
+    }
+
+    /// the commands in a `buildozer -f` command line, read before the file
+    /// is removed.
+    fn command_file(command: &str) -> Option<String> {
+        let path = command.strip_prefix("buildozer -f ")?;
+        Some(std::fs::read_to_string(path).unwrap())
+    }
+
+    #[test]
+    fn long_buildozer_commands_are_passed_in_a_file() {
+        let commands = Rc::new(RefCell::new(vec![]));
+        let recorded = commands.clone();
+        let runner = FakeRunner::new(move |command| {
+            recorded.borrow_mut().push(command_file(command)?);
+            if command_file(command)?.starts_with("print deps") {
+                succeeded("[//a:lib]\n")
+            } else {
+                succeeded("")
+            }
+        })
+        .with_argv_limit(16);
+        assert_eq!(get_deps(&runner, "//x:bin").unwrap(), vec!["//a:lib"]);
+        let result = try_remove_dep(&runner, "//x:bin", "//a:lib", &[]).unwrap();
+        assert!(result.removable);
+        assert_eq!(
+            *commands.borrow(),
+            vec![
+                "print deps|//x:bin\n",
+                "remove deps //a:lib|//x:bin\n",
+                "add deps //a:lib|//x:bin\n"
+            ]
+        );
+        // the command files are removed once buildozer has run.
+        for call in runner.calls.borrow().iter() {
+            let path = call.strip_prefix("buildozer -f ").unwrap();
+            assert!(!std::path::Path::new(path).exists());
+        }
+    }
+
+    #[test]
+    fn try_remove_dep_errors_when_bazel_cannot_run() {
+        let runner = FakeRunner::new(|command| {
+            if command.starts_with("buildozer") {
+                succeeded("")
+            } else {
+                None
+            }
+        });
+        let err =
+            try_remove_dep(&runner, "//x:bin", "//a:lib", &["//x:test".to_string()]).unwrap_err();
+        assert!(matches!(err, DepsawError::CommandFailed(_)));
+        assert!(err.to_string().contains("bazel test //x:test"));
+        // the dep is still added back.
+        assert_eq!(
+            runner.calls.borrow().last().unwrap(),
+            "buildozer add deps //a:lib //x:bin"
+        );
+    }
+
+    fn tests(labels: &[&str]) -> Vec<String> {
+        labels.iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn try_remove_dep_reports_failing_tests() {
+        let runner = FakeRunner::new(|command| match command {
+            "bazel test //x:slow_test" => failed("FAILED"),
+            _ => succeeded(""),
+        });
+        let result = try_remove_dep(
+            &runner,
+            "//x:bin",
+            "//a:lib",
+            &tests(&["//x:test", "//x:slow_test"]),
+        )
+        .unwrap();
+        assert!(!result.removable);
+        assert_eq!(result.failing_tests, vec!["//x:slow_test"]);
+        assert_eq!(result.error, None);
+        assert_eq!(
+            *runner.calls.borrow(),
+            vec![
+                "buildozer remove deps //a:lib //x:bin",
+                "bazel test //x:test",
+                "bazel test //x:slow_test",
+                "buildozer add deps //a:lib //x:bin",
+            ]
+        );
+    }
 
-        if !output.status.success() {
-            success = false;
-            error!(
-                "bazel test failed: {}",
-                String::from_utf8_lossy(&output.stderr)
+    #[test]
+    fn failed_removal_is_not_removable() {
+        let runner = FakeRunner::new(|command| {
+            if command.starts_with("buildozer remove") {
+                failed("rule not found")
+            } else {
+                succeeded("")
+            }
+        });
+        let result = try_remove_dep(&runner, "//x:bin", "//a:lib", &tests(&["//x:test"])).unwrap();
+        assert!(!result.removable);
+        assert!(result.error.unwrap().contains("rule not found"));
+        // neither the tests nor the re-add run.
+        assert_eq!(runner.calls.borrow().len(), 1);
+    }
+
+    #[test]
+    fn failed_re_add_is_an_error() {
+        let runner = FakeRunner::new(|command| {
+            if command.starts_with("buildozer add") {
+                failed("permission denied")
+            } else {
+                succeeded("")
+            }
+        });
+        let err = try_remove_dep(&runner, "//x:bin", "//a:lib", &tests(&["//x:test"])).unwrap_err();
+        assert!(matches!(err, DepsawError::CommandFailed(_)));
+        assert!(err.to_string().contains("left without it"));
+    }
+
+    fn results() -> Vec<RemovableDepResult> {
+        vec![
+            RemovableDepResult {
+                dep: "//a:lib".to_string(),
+                removable: false,
+                failing_tests: tests(&["//x:test", "//y:test"]),
+                duration_secs: 1.5,
+                error: None,
+            },
+            RemovableDepResult {
+                dep: "//b:lib".to_string(),
+                removable: true,
+                failing_tests: vec![],
+                duration_secs: 0.25,
+                error: None,
+            },
+            RemovableDepResult {
+                dep: "//c:lib".to_string(),
+                removable: false,
+                failing_tests: vec![],
+                duration_secs: 0.0,
+                error: Some("buildozer failed".to_string()),
+            },
+        ]
+    }
+
+    #[test]
+    fn results_round_trip_through_yaml_and_json() {
+        let yaml = serde_yaml::to_string(&results()).unwrap();
+        assert_eq!(
+            serde_yaml::from_str::<Vec<RemovableDepResult>>(&yaml).unwrap(),
+            results()
+        );
+        let json = serde_json::to_string(&results()).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Vec<RemovableDepResult>>(&json).unwrap(),
+            results()
+        );
+    }
+
+    #[test]
+    fn results_round_trip_through_csv() {
+        let mut wtr = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(vec![]);
+        for result in results() {
+            wtr.serialize(result).unwrap();
+        }
+        let csv = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            csv,
+            "//a:lib,false,//x:test;//y:test,1.5,\n//b:lib,true,,0.25,\n//c:lib,false,,0.0,buildozer failed\n"
+        );
+        let parsed: Vec<RemovableDepResult> = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(csv.as_bytes())
+            .deserialize()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap();
+        assert_eq!(parsed, results());
+    }
+
+    mod suggestions {
+        use super::*;
+        use crate::algorithms::{most_unique_triggers, removal_suggestions};
+        use crate::testing::{graph, repo, rule, source, TempDir};
+
+        /// most-unique-triggers --suggestions-output, then removable-deps
+        /// --from-suggestions, against a scripted buildozer and bazel.
+        #[test]
+        fn verifies_top_suggestions_end_to_end() {
+            let deps_graph = graph(&[
+                rule("//app:bin", "java_binary", &["//w:wrapper", "//b:lib"]),
+                rule("//w:wrapper", "java_library", &["//a:lib"]),
+                rule("//w:wrapper_test", "java_test", &["//w:wrapper"]),
+                rule("//a:lib", "java_library", &["//a:A.java"]),
+                rule("//b:lib", "java_library", &["//b:B.java"]),
+                source("//a:A.java"),
+                source("//b:B.java"),
+            ]);
+            let git_repo = repo(&[("a/A.java", &["c1", "c2"]), ("b/B.java", &["c3"])]);
+            let deps = most_unique_triggers("//app:bin", &git_repo, &deps_graph, 2).unwrap();
+            let dir = TempDir::new("suggestions");
+            let path = dir.join("suggestions.yaml");
+            removal_suggestions("//app:bin", &deps, &deps_graph)
+                .unwrap()
+                .to_file(&path)
+                .unwrap();
+            let suggestions = Suggestions::from_file(&path).unwrap();
+
+            let runner = FakeRunner::new(|command| match command {
+                "bazel query kind('.*_test rule', rdeps(//..., //app:bin)) --output label" => {
+                    succeeded("//app:bin_test\n")
+                }
+                "bazel test //w:wrapper_test" => failed("FAILED"),
+                _ => succeeded(""),
+            });
+            let candidates = candidates_from_suggestions(&runner, &suggestions, 2, &[]).unwrap();
+            // highest score first, //a:lib being the only path to c1 and c2.
+            assert_eq!(
+                candidates,
+                vec![
+                    Candidate {
+                        from_target: "//w:wrapper".to_string(),
+                        dep: "//a:lib".to_string(),
+                        tests: tests(&["//w:wrapper_test"]),
+                    },
+                    Candidate {
+                        from_target: "//app:bin".to_string(),
+                        dep: "//w:wrapper".to_string(),
+                        tests: tests(&["//app:bin_test"]),
+                    },
+                ]
             );
+            let results = verify_removals(&runner, &candidates).unwrap();
+            let removable: Vec<_> = results
+                .iter()
+                .map(|r| (r.dep.as_str(), r.removable))
+                .collect();
+            assert_eq!(removable, vec![("//a:lib", false), ("//w:wrapper", true)]);
+            assert_eq!(results[0].failing_tests, vec!["//w:wrapper_test"]);
+            // both deps are put back after their tests ran.
+            let calls = runner.calls.borrow();
+            assert!(calls.contains(&"buildozer add deps //a:lib //w:wrapper".to_string()));
+            assert!(calls.contains(&"buildozer add deps //w:wrapper //app:bin".to_string()));
+        }
+
+        #[test]
+        fn candidates_prefer_given_tests() {
+            let suggestions: Suggestions = serde_yaml::from_str(
+                "target: //app:bin
+suggestions:
+- dep: //a:lib
+  from_target: //app:bin
+  score: 1
+  confidence: low_risk
+  buildozer_command: buildozer 'remove deps //a:lib' //app:bin
+  verify_tests: [//app:graph_test]
+- dep: //b:lib
+  from_target: //app:bin
+  score: 5
+  confidence: do_not_touch
+  buildozer_command: buildozer 'remove deps //b:lib' //app:bin
+  verify_tests: []
+",
+            )
+            .unwrap();
+            let runner = FakeRunner::new(|_| None);
+            let given = tests(&["//app:given_test"]);
+            let candidates =
+                candidates_from_suggestions(&runner, &suggestions, 10, &given).unwrap();
+            // do_not_touch suggestions are never verified.
+            assert_eq!(
+                candidates,
+                vec![Candidate {
+                    from_target: "//app:bin".to_string(),
+                    dep: "//a:lib".to_string(),
+                    tests: given,
+                }]
+            );
+            let candidates = candidates_from_suggestions(&runner, &suggestions, 10, &[]).unwrap();
+            assert_eq!(candidates[0].tests, vec!["//app:graph_test"]);
+            assert!(runner.calls.borrow().is_empty());
+        }
+
+        #[test]
+        fn candidates_without_tests_are_rejected() {
+            let runner = FakeRunner::new(|_| succeeded(""));
+            let candidates = vec![Candidate {
+                from_target: "//app:bin".to_string(),
+                dep: "//a:lib".to_string(),
+                tests: vec![],
+            }];
+            let err = verify_removals(&runner, &candidates).unwrap_err();
+            assert!(err.to_string().contains("--test"));
+            assert!(runner.calls.borrow().is_empty());
         }
     }
-    // re-add the dep at the end
-    add_dep(target, dep);
-    success
 }
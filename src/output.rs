@@ -0,0 +1,238 @@
+//! Rendering analysis results.
+//!
+//! A result is rendered to stdout in the format given by `--format`, and
+//! additionally to each `--also-output` file, all from the same in-memory
+//! result so the analysis only runs once. `--top` limits the rows written to
+//! stdout, and only limits the additional outputs with `--top-all-outputs`.
+use super::error::{DepsawError, Result};
+use serde::Serialize;
+use std::io::Write;
+use std::str::FromStr;
+use tracing::info;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Yaml,
+    Json,
+    /// one row per result, without headers.
+    Csv,
+}
+
+/// an additional output, written as `<format>=<path>`.
+#[derive(Debug, Clone)]
+pub struct AlsoOutput {
+    pub format: Format,
+    pub path: String,
+}
+
+impl FromStr for AlsoOutput {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        let (format, path) = value
+            .split_once('=')
+            .ok_or_else(|| format!("expected <format>=<path>, got {}", value))?;
+        let format = <Format as clap::ValueEnum>::from_str(format, true)
+            .map_err(|_| format!("unsupported format {}, expected yaml, json, or csv", format))?;
+        if path.is_empty() {
+            return Err(format!("missing path for {} output", value));
+        }
+        Ok(AlsoOutput {
+            format,
+            path: path.to_string(),
+        })
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct OutputArgs {
+    /// The format to output the results in
+    #[arg(long, value_enum, default_value_t = Format::Yaml)]
+    pub format: Format,
+
+    /// Also write the results to a file, as `<format>=<path>`. Can be
+    /// repeated
+    #[arg(long)]
+    pub also_output: Vec<AlsoOutput>,
+
+    /// Only write the first N results to stdout
+    #[arg(long)]
+    pub top: Option<usize>,
+
+    /// Apply --top to the --also-output files too
+    #[arg(long, requires = "top")]
+    pub top_all_outputs: bool,
+}
+
+impl OutputArgs {
+    /// write the results to stdout and every additional output.
+    pub fn write<T: Serialize>(&self, items: &[T]) -> Result<()> {
+        self.write_to(items, &mut std::io::stdout().lock())
+    }
+
+    fn write_to<T: Serialize>(&self, items: &[T], stdout: &mut impl Write) -> Result<()> {
+        let limited = &items[..self.top.map_or(items.len(), |top| top.min(items.len()))];
+        render(limited, self.format, stdout)?;
+        stdout.flush()?;
+        for output in self.also_output.iter() {
+            let items = if self.top_all_outputs { limited } else { items };
+            let mut content = Vec::new();
+            render(items, output.format, &mut content)?;
+            std::fs::write(&output.path, content).map_err(|e| {
+                DepsawError::IoError(std::io::Error::new(
+                    e.kind(),
+                    format!("unable to write {}: {}", output.path, e),
+                ))
+            })?;
+            info!("wrote {} results to {}", items.len(), output.path);
+        }
+        Ok(())
+    }
+}
+
+fn render<T: Serialize>(items: &[T], format: Format, writer: &mut impl Write) -> Result<()> {
+    match format {
+        Format::Yaml => {
+            writeln!(writer, "{}", serde_yaml::to_string(items)?)?;
+        }
+        Format::Json => {
+            writeln!(writer, "{}", serde_json::to_string_pretty(items)?)?;
+        }
+        Format::Csv => {
+            // list fields are flattened into the row, so rows may differ in
+            // length.
+            let mut wtr = csv::WriterBuilder::new()
+                .has_headers(false)
+                .flexible(true)
+                .from_writer(writer);
+            // Serialize each result as a row
+            for item in items {
+                wtr.serialize(item)?;
+            }
+            wtr.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// serde helpers for a list of strings written as a single `;` separated
+/// string, for fields that need to stay in one CSV cell.
+pub mod delimited {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    const SEPARATOR: char = ';';
+
+    pub fn serialize<S: Serializer>(values: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&values.join(&SEPARATOR.to_string()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<String>, D::Error> {
+        let joined = String::deserialize(deserializer)?;
+        Ok(joined
+            .split(SEPARATOR)
+            .filter(|value| !value.is_empty())
+            .map(|value| value.to_string())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[derive(Serialize)]
+    struct Row {
+        name: String,
+        score: usize,
+    }
+
+    fn rows() -> Vec<Row> {
+        (1..=3)
+            .map(|i| Row {
+                name: format!("//{}:lib", i),
+                score: i,
+            })
+            .collect()
+    }
+
+    fn output_args(
+        top: Option<usize>,
+        top_all_outputs: bool,
+        also_output: &[String],
+    ) -> OutputArgs {
+        OutputArgs {
+            format: Format::Csv,
+            also_output: also_output.iter().map(|o| o.parse().unwrap()).collect(),
+            top,
+            top_all_outputs,
+        }
+    }
+
+    #[test]
+    fn also_output_parses_format_and_path() {
+        let output: AlsoOutput = "json=/tmp/scores.json".parse().unwrap();
+        assert_eq!(output.format, Format::Json);
+        assert_eq!(output.path, "/tmp/scores.json");
+        let output: AlsoOutput = "CSV=a=b.csv".parse().unwrap();
+        assert_eq!(output.format, Format::Csv);
+        assert_eq!(output.path, "a=b.csv");
+        assert!("scores.json"
+            .parse::<AlsoOutput>()
+            .unwrap_err()
+            .contains("<format>=<path>"));
+        assert!("xml=scores.xml"
+            .parse::<AlsoOutput>()
+            .unwrap_err()
+            .contains("unsupported format xml"));
+        assert!("json="
+            .parse::<AlsoOutput>()
+            .unwrap_err()
+            .contains("missing path"));
+    }
+
+    #[test]
+    fn top_only_limits_stdout() {
+        let dir = TempDir::new("also-output");
+        let json = dir.join("scores.json");
+        let yaml = dir.join("scores.yaml");
+        let args = output_args(
+            Some(1),
+            false,
+            &[format!("json={}", json), format!("yaml={}", yaml)],
+        );
+        let mut stdout = Vec::new();
+        args.write_to(&rows(), &mut stdout).unwrap();
+        assert_eq!(String::from_utf8(stdout).unwrap(), "//1:lib,1\n");
+
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+        assert_eq!(written.as_array().unwrap().len(), 3);
+        let written: serde_yaml::Value =
+            serde_yaml::from_str(&std::fs::read_to_string(&yaml).unwrap()).unwrap();
+        assert_eq!(written.as_sequence().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn top_all_outputs_limits_every_output() {
+        let dir = TempDir::new("top-all-outputs");
+        let csv = dir.join("scores.csv");
+        let args = output_args(Some(2), true, &[format!("csv={}", csv)]);
+        let mut stdout = Vec::new();
+        args.write_to(&rows(), &mut stdout).unwrap();
+        let expected = "//1:lib,1\n//2:lib,2\n";
+        assert_eq!(String::from_utf8(stdout).unwrap(), expected);
+        assert_eq!(std::fs::read_to_string(&csv).unwrap(), expected);
+    }
+
+    #[test]
+    fn unwritable_outputs_name_the_path() {
+        let dir = TempDir::new("unwritable");
+        let path = dir.join("missing/scores.json");
+        let args = output_args(None, false, &[format!("json={}", path)]);
+        let err = args.write_to(&rows(), &mut Vec::new()).unwrap_err();
+        assert!(err.to_string().contains(&path));
+    }
+}
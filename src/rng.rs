@@ -0,0 +1,161 @@
+//! The source of randomness for every randomized component.
+//!
+//! Components never create their own random number generators. They ask the
+//! run's `RngFactory` for one by name instead, so that every generator is
+//! derived from the run's `--seed`, and two runs with the same seed make the
+//! same random choices. Without `--seed`, the seed is picked from the clock
+//! and logged, so a run can still be reproduced.
+//!
+//! The generator is SplitMix64, which is small, fast and good enough for
+//! shuffling and sampling. It is not suitable for anything security related.
+use tracing::info;
+
+/// A seeded pseudo random number generator.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// a number in `0..n`. `n` must not be zero.
+    pub fn below(&mut self, n: u64) -> u64 {
+        // the bias is negligible for the small ranges shuffled here.
+        self.next_u64() % n
+    }
+
+    /// shuffle the items in place, with Fisher-Yates.
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.below(i as u64 + 1) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+/// Creates the generators of a run from its seed.
+#[derive(Debug, Clone)]
+pub struct RngFactory {
+    seed: u64,
+}
+
+impl RngFactory {
+    /// a factory for the given seed, or a seed picked from the clock.
+    pub fn new(seed: Option<u64>) -> RngFactory {
+        let seed = seed.unwrap_or_else(|| {
+            let seed = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64);
+            info!(
+                "using seed {}, pass --seed {} to reproduce this run",
+                seed, seed
+            );
+            seed
+        });
+        RngFactory { seed }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// the generator of a component. Each component gets its own stream, so
+    /// adding randomness to one component doesn't change the choices of the
+    /// others.
+    pub fn rng(&self, component: &str) -> Rng {
+        // FNV-1a, as the std hashers aren't guaranteed to be stable.
+        let hash = component.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, b| {
+            (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+        let mut mixer = Rng::new(self.seed ^ hash);
+        Rng::new(mixer.next_u64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shuffled(factory: &RngFactory, component: &str) -> Vec<usize> {
+        let mut items: Vec<usize> = (0..20).collect();
+        factory.rng(component).shuffle(&mut items);
+        items
+    }
+
+    #[test]
+    fn same_seed_makes_the_same_choices() {
+        let first = RngFactory::new(Some(42));
+        let second = RngFactory::new(Some(42));
+        assert_eq!(shuffled(&first, "sampling"), shuffled(&second, "sampling"));
+        assert_ne!(shuffled(&first, "sampling"), (0..20).collect::<Vec<_>>());
+        assert_ne!(
+            shuffled(&first, "sampling"),
+            shuffled(&RngFactory::new(Some(43)), "sampling")
+        );
+    }
+
+    #[test]
+    fn components_get_independent_streams() {
+        let factory = RngFactory::new(Some(42));
+        assert_ne!(
+            shuffled(&factory, "sampling"),
+            shuffled(&factory, "clustering")
+        );
+        let mut shuffled = shuffled(&factory, "sampling");
+        shuffled.sort();
+        assert_eq!(shuffled, (0..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn below_stays_in_range() {
+        let mut rng = RngFactory::new(Some(7)).rng("range");
+        assert!((0..1000).all(|_| rng.below(3) < 3));
+    }
+
+    /// every generator must come from the factory, so nothing may seed
+    /// itself from the OS.
+    #[test]
+    fn randomness_only_comes_from_the_factory() {
+        let forbidden = [
+            concat!("thread", "_rng("),
+            concat!("rand::", "random"),
+            concat!("from_", "entropy("),
+            concat!("Random", "State::new("),
+        ];
+        let mut stack = vec![std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src")];
+        let mut checked = 0;
+        while let Some(path) = stack.pop() {
+            if path.is_dir() {
+                for entry in std::fs::read_dir(&path).unwrap() {
+                    stack.push(entry.unwrap().path());
+                }
+                continue;
+            }
+            if path.extension().is_none_or(|e| e != "rs") {
+                continue;
+            }
+            let content = std::fs::read_to_string(&path).unwrap();
+            for pattern in forbidden {
+                assert!(
+                    !content.contains(pattern),
+                    "{} uses {}, get a generator from rng::RngFactory instead",
+                    path.display(),
+                    pattern
+                );
+            }
+            checked += 1;
+        }
+        assert!(checked > 10);
+    }
+}
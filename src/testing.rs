@@ -0,0 +1,181 @@
+//! Fixtures shared by the unit tests.
+use super::bazel::{BazelDependencyGraph, ParseOptions};
+use super::git::{GitFile, GitRepo};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static TEMP_DIR_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// a directory in the temporary directory, removed when dropped.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!(
+            "depsaw-test-{}-{}-{}",
+            std::process::id(),
+            TEMP_DIR_COUNTER.fetch_add(1, Ordering::Relaxed),
+            name
+        ));
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn path_str(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+
+    pub fn join(&self, relative_path: &str) -> String {
+        self.path.join(relative_path).to_str().unwrap().to_string()
+    }
+
+    /// write a file, creating its parent directories.
+    pub fn write(&self, relative_path: &str, contents: &str) {
+        let path = self.path.join(relative_path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// run git in the directory, isolated from the user's configuration,
+/// returning its stdout.
+pub fn git(dir: &TempDir, args: &[&str]) -> String {
+    git_with_env(dir, args, &[])
+}
+
+fn git_with_env(dir: &TempDir, args: &[&str], env: &[(&str, &str)]) -> String {
+    let output = Command::new("git")
+        .current_dir(dir.path())
+        .args(args)
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_AUTHOR_NAME", "depsaw")
+        .env("GIT_AUTHOR_EMAIL", "depsaw@example.com")
+        .env("GIT_COMMITTER_NAME", "depsaw")
+        .env("GIT_COMMITTER_EMAIL", "depsaw@example.com")
+        .envs(env.iter().copied())
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "git {} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// an empty git repository.
+pub fn git_repo(name: &str) -> TempDir {
+    let dir = TempDir::new(name);
+    git(&dir, &["init", "-q", "-b", "main"]);
+    dir
+}
+
+/// write the files and commit them, returning the commit hash.
+pub fn commit(dir: &TempDir, files: &[(&str, &str)], message: &str) -> String {
+    commit_at(dir, files, message, None)
+}
+
+/// like `commit`, dating the commit in git's date format when given.
+pub fn commit_at(
+    dir: &TempDir,
+    files: &[(&str, &str)],
+    message: &str,
+    date: Option<&str>,
+) -> String {
+    for (path, contents) in files {
+        dir.write(path, contents);
+    }
+    git(dir, &["add", "-A"]);
+    let env: Vec<(&str, &str)> = date
+        .map(|date| vec![("GIT_AUTHOR_DATE", date), ("GIT_COMMITTER_DATE", date)])
+        .unwrap_or_default();
+    git_with_env(dir, &["commit", "-q", "-m", message], &env);
+    git(dir, &["rev-parse", "HEAD"]).trim().to_string()
+}
+
+/// a `RULE` line of `bazel query --output streamed_jsonproto` output.
+pub fn rule(name: &str, rule_class: &str, inputs: &[&str]) -> String {
+    serde_json::json!({
+        "type": "RULE",
+        "rule": {"name": name, "ruleClass": rule_class, "ruleInput": inputs},
+    })
+    .to_string()
+}
+
+/// a java_library `RULE` line with exports and visibility, its inputs and
+/// attribute lists in the given order.
+pub fn rule_with_attributes(
+    name: &str,
+    inputs: &[&str],
+    exports: &[&str],
+    visibility: &[&str],
+) -> String {
+    serde_json::json!({
+        "type": "RULE",
+        "rule": {
+            "name": name,
+            "ruleClass": "java_library",
+            "ruleInput": inputs,
+            "attribute": [
+                {"name": "exports", "stringListValue": exports},
+                {"name": "visibility", "stringListValue": visibility},
+            ],
+        },
+    })
+    .to_string()
+}
+
+/// a `SOURCE_FILE` line of bazel query output.
+pub fn source(name: &str) -> String {
+    serde_json::json!({"type": "SOURCE_FILE", "sourceFile": {"name": name}}).to_string()
+}
+
+/// a `GENERATED_FILE` line of bazel query output.
+pub fn generated(name: &str, generating_rule: &str) -> String {
+    serde_json::json!({
+        "type": "GENERATED_FILE",
+        "generatedFile": {"name": name, "generatingRule": generating_rule},
+    })
+    .to_string()
+}
+
+pub fn graph(lines: &[String]) -> BazelDependencyGraph {
+    BazelDependencyGraph::from_string(&lines.join("\n"), ParseOptions::default()).unwrap()
+}
+
+/// a git analysis from the commits touching each path.
+pub fn repo(files: &[(&str, &[&str])]) -> GitRepo {
+    GitRepo {
+        files: files
+            .iter()
+            .map(|(path, commits)| {
+                (
+                    path.to_string(),
+                    GitFile {
+                        commit_history: commits.iter().map(|c| c.to_string()).collect(),
+                    },
+                )
+            })
+            .collect(),
+        last_commit: None,
+        options: None,
+        commit_times: HashMap::new(),
+        window: None,
+    }
+}
@@ -0,0 +1,309 @@
+//! Checking GitHub releases for a newer version of depsaw.
+//!
+//! The latest release is fetched with `curl`, the same way other external
+//! work is delegated to subprocesses, so depsaw doesn't need an HTTP client.
+//! The passive check never runs in CI. It refreshes the latest release at
+//! most once a week in a background thread, so the run isn't held up by the
+//! network, and caches it in the user's cache directory so later runs can
+//! show the notice without fetching.
+use super::analysis_file;
+use super::error::{DepsawError, Result};
+use super::metrics;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+use tracing::{debug, warn};
+
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/toumorokoshi/depsaw/releases/latest";
+const RELEASES_PAGE: &str = "https://github.com/toumorokoshi/depsaw/releases";
+/// setting this environment variable disables the passive check.
+const NO_UPDATE_CHECK_ENV: &str = "DEPSAW_NO_UPDATE_CHECK";
+const PASSIVE_CHECK_INTERVAL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// the first release writing each analysis file schema version, oldest
+/// first. Add an entry whenever `analysis_file::SCHEMA_VERSION` is bumped.
+const SCHEMA_BY_RELEASE: [(&str, u32); 1] = [("0.1.0", 4)];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum UpdateCheck {
+    /// never check for updates, unless running `check-update`.
+    Never,
+    /// check for updates at most once a week, outside of CI.
+    Auto,
+}
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+}
+
+/// the explicit `check-update` command, which fails if the latest release
+/// can't be fetched.
+pub fn check_update() -> Result<()> {
+    let latest = latest_release(LATEST_RELEASE_URL, Duration::from_secs(30))?;
+    match upgrade_notice(&latest) {
+        Some(notice) => println!("{}", notice),
+        None => println!("depsaw {} is up to date", env!("CARGO_PKG_VERSION")),
+    }
+    Ok(())
+}
+
+/// the passive check, which logs a notice when a newer release is available
+/// and otherwise stays silent, including on network failures. Returns the
+/// background refresh of the latest release, if one was started, to be
+/// joined at the end of the run.
+pub fn passive_check(mode: UpdateCheck) -> Option<JoinHandle<()>> {
+    if mode == UpdateCheck::Never
+        || std::env::var_os(NO_UPDATE_CHECK_ENV).is_some()
+        || std::env::var_os("CI").is_some()
+    {
+        return None;
+    }
+    passive_check_with(stamp_path()?, LATEST_RELEASE_URL.to_string())
+}
+
+fn passive_check_with(stamp: PathBuf, url: String) -> Option<JoinHandle<()>> {
+    let cached = std::fs::read_to_string(&stamp).ok();
+    if let Some(notice) = cached
+        .as_deref()
+        .and_then(|latest| upgrade_notice(latest.trim()))
+    {
+        warn!("{}", notice);
+    }
+    let checked_recently = std::fs::metadata(&stamp)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|elapsed| elapsed < PASSIVE_CHECK_INTERVAL);
+    if checked_recently {
+        return None;
+    }
+    // record the attempt right away, so failures are retried a week later
+    // rather than on every run.
+    record_latest(&stamp, cached.as_deref().unwrap_or_default());
+    Some(std::thread::spawn(move || {
+        match latest_release(&url, Duration::from_secs(5)) {
+            Ok(latest) => {
+                record_latest(&stamp, &latest);
+                // the cached release was already reported.
+                if cached.as_deref().map(str::trim) != Some(latest.as_str()) {
+                    if let Some(notice) = upgrade_notice(&latest) {
+                        warn!("{}", notice);
+                    }
+                }
+            }
+            Err(e) => debug!("update check failed: {}", e),
+        }
+    }))
+}
+
+fn record_latest(stamp: &Path, latest: &str) {
+    if let Err(e) = stamp
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(stamp, latest))
+    {
+        debug!("unable to record update check in {:?}: {}", stamp, e);
+    }
+}
+
+fn stamp_path() -> Option<PathBuf> {
+    let cache_dir = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(cache_dir.join("depsaw").join("last-update-check"))
+}
+
+fn latest_release(url: &str, timeout: Duration) -> Result<String> {
+    debug!("fetching {}", url);
+    metrics::record_subprocess();
+    let output = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--location"])
+        .args(["--max-time", &timeout.as_secs().to_string()])
+        .args(["--header", "Accept: application/vnd.github+json"])
+        .arg(url)
+        .output()
+        .map_err(|e| DepsawError::UpdateCheckFailed(format!("unable to run curl: {}", e)))?;
+    if !output.status.success() {
+        return Err(DepsawError::UpdateCheckFailed(format!(
+            "{}: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let release: Release = serde_json::from_slice(&output.stdout)?;
+    Ok(release.tag_name.trim_start_matches('v').to_string())
+}
+
+/// the upgrade instructions when the latest release is newer than this one.
+fn upgrade_notice(latest: &str) -> Option<String> {
+    let current = env!("CARGO_PKG_VERSION");
+    if parse_version(latest)? <= parse_version(current)? {
+        return None;
+    }
+    let precalculate = match schema_version(latest) {
+        Some(schema) if schema == analysis_file::SCHEMA_VERSION => {
+            "Existing analysis files can still be read after upgrading".to_string()
+        }
+        Some(schema) => format!(
+            "It writes analysis files with schema {} instead of {}, so re-run precalculate after upgrading",
+            schema,
+            analysis_file::SCHEMA_VERSION
+        ),
+        None => "Re-run precalculate after upgrading if loading existing analysis files fails"
+            .to_string(),
+    };
+    Some(format!(
+        "depsaw {} is available (you have {}). Download it from {}. {}",
+        latest, current, RELEASES_PAGE, precalculate
+    ))
+}
+
+/// the analysis file schema written by a release, if known. Releases newer
+/// than the last entry of the table are unknown, as they may have changed
+/// it.
+fn schema_version(release: &str) -> Option<u32> {
+    let release = parse_version(release)?;
+    let (last, _) = SCHEMA_BY_RELEASE.last()?;
+    if release > parse_version(last)? {
+        return None;
+    }
+    SCHEMA_BY_RELEASE
+        .iter()
+        .rev()
+        .find(|(first, _)| parse_version(first).is_some_and(|first| first <= release))
+        .map(|(_, schema)| *schema)
+}
+
+/// parse the major, minor and patch numbers of a semver version, ignoring
+/// any pre-release or build metadata.
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let core = version.split(['-', '+']).next()?;
+    let mut parts = core.split('.').map(|p| p.parse::<u64>().ok());
+    let version = (parts.next()??, parts.next()??, parts.next()??);
+    match parts.next() {
+        None => Some(version),
+        Some(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// serve a single HTTP response on a local port, returning its url.
+    fn serve(status: &str, body: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/releases/latest", listener.local_addr().unwrap());
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request);
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        url
+    }
+
+    #[test]
+    fn versions_are_parsed_as_semver() {
+        assert_eq!(parse_version("1.2.3"), Some((1, 2, 3)));
+        assert_eq!(parse_version("1.2.3-rc.1"), Some((1, 2, 3)));
+        assert_eq!(parse_version("1.2.3+build.5"), Some((1, 2, 3)));
+        assert_eq!(parse_version("0.10.0"), Some((0, 10, 0)));
+        assert!(parse_version("0.10.0") > parse_version("0.9.9"));
+        for invalid in ["1.2", "1.2.3.4", "v1.2.3", "1.x.3", ""] {
+            assert_eq!(parse_version(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn notices_only_newer_releases() {
+        assert_eq!(upgrade_notice(env!("CARGO_PKG_VERSION")), None);
+        assert_eq!(upgrade_notice("0.0.1"), None);
+        assert_eq!(upgrade_notice("not a version"), None);
+        let notice = upgrade_notice("999.0.0").unwrap();
+        assert!(notice.contains("depsaw 999.0.0 is available"));
+        assert!(notice.contains(RELEASES_PAGE));
+    }
+
+    #[test]
+    fn schema_table_covers_this_release() {
+        assert_eq!(
+            schema_version(env!("CARGO_PKG_VERSION")),
+            Some(analysis_file::SCHEMA_VERSION)
+        );
+        assert_eq!(schema_version("999.0.0"), None);
+        let mut releases: Vec<_> = SCHEMA_BY_RELEASE
+            .iter()
+            .map(|(r, _)| parse_version(r))
+            .collect();
+        assert!(releases.iter().all(|r| r.is_some()));
+        let sorted = releases.clone();
+        releases.sort();
+        assert_eq!(releases, sorted);
+    }
+
+    #[test]
+    fn latest_release_is_fetched() {
+        let url = serve(
+            "200 OK",
+            r#"{"tag_name": "v1.2.3", "name": "depsaw 1.2.3"}"#,
+        );
+        assert_eq!(
+            latest_release(&url, Duration::from_secs(5)).unwrap(),
+            "1.2.3"
+        );
+    }
+
+    #[test]
+    fn failed_fetches_are_errors() {
+        let url = serve("404 Not Found", r#"{"message": "Not Found"}"#);
+        let err = latest_release(&url, Duration::from_secs(5)).unwrap_err();
+        assert!(matches!(err, DepsawError::UpdateCheckFailed(_)));
+        let url = serve("200 OK", "not json");
+        assert!(latest_release(&url, Duration::from_secs(5)).is_err());
+    }
+
+    #[test]
+    fn passive_check_caches_the_latest_release() {
+        let dir = TempDir::new("update-check");
+        let stamp = dir.path().join("depsaw").join("last-update-check");
+        let url = serve("200 OK", r#"{"tag_name": "v999.0.0"}"#);
+        passive_check_with(stamp.clone(), url.clone())
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&stamp).unwrap(), "999.0.0");
+        // checked recently, so nothing is fetched.
+        assert!(passive_check_with(stamp.clone(), url.clone()).is_none());
+    }
+
+    #[test]
+    fn passive_check_is_silent_on_network_failures() {
+        let dir = TempDir::new("update-check-offline");
+        let stamp = dir.path().join("last-update-check");
+        // nothing listens on the port once the listener is dropped.
+        let url = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}/", listener.local_addr().unwrap())
+        };
+        passive_check_with(stamp.clone(), url.clone())
+            .unwrap()
+            .join()
+            .unwrap();
+        // the attempt is still recorded, so it isn't retried on every run.
+        assert_eq!(std::fs::read_to_string(&stamp).unwrap(), "");
+        assert!(passive_check_with(stamp, url).is_none());
+    }
+}
@@ -0,0 +1,207 @@
+//! Reading files under the workspace root.
+//!
+//! Analysis that reads workspace files should not abort on the first file it
+//! cannot read, such as a root-owned leftover from another build. IO errors
+//! on individual paths are instead collected as skipped paths, to be
+//! reported alongside the results, unless strict IO was requested, in which
+//! case the first one is fatal.
+//!
+//! Missing files are counted separately and never fatal: the dependency
+//! graph routinely refers to sources that only exist once generated.
+//!
+//! Whether a source file is read to count its lines is decided by
+//! `git::FileRelevance`, so untracked and ignored files in a developer's
+//! tree don't change the line counts.
+use super::error::{DepsawError, Result};
+use super::git::{FileRelevance, FileStatus};
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SkippedPath {
+    /// the path, relative to the workspace root.
+    pub path: String,
+    /// the kind of IO error that caused the path to be skipped.
+    pub error_kind: String,
+}
+
+pub struct Workspace {
+    root: PathBuf,
+    strict_io: bool,
+    missing_paths: usize,
+    skipped_paths: Vec<SkippedPath>,
+    /// created on first use, since it runs git.
+    relevance: Option<FileRelevance>,
+    untracked_paths: usize,
+    ignored_paths: usize,
+}
+
+impl Workspace {
+    pub fn new(root: &str, strict_io: bool) -> Workspace {
+        Workspace {
+            root: PathBuf::from(root),
+            strict_io,
+            missing_paths: 0,
+            skipped_paths: vec![],
+            relevance: None,
+            untracked_paths: 0,
+            ignored_paths: 0,
+        }
+    }
+
+    fn relevance(&mut self) -> Result<&mut FileRelevance> {
+        if self.relevance.is_none() {
+            self.relevance = Some(FileRelevance::new(&self.root.to_string_lossy())?);
+        }
+        Ok(self.relevance.as_mut().unwrap())
+    }
+
+    /// check whether the workspace-relative paths are relevant in a single
+    /// batch, ahead of calling `is_relevant` on each.
+    pub fn check_relevance(&mut self, relative_paths: &[String]) -> Result<()> {
+        self.relevance()?.check(relative_paths)
+    }
+
+    /// whether a workspace-relative path is tracked by git, and so should be
+    /// read. Untracked and ignored paths are counted.
+    pub fn is_relevant(&mut self, relative_path: &str) -> Result<bool> {
+        let status = self.relevance()?.status(relative_path)?;
+        // paths that don't exist are left to `read_with`, which counts them
+        // as missing.
+        if status != FileStatus::Tracked && !self.root.join(relative_path).exists() {
+            return Ok(true);
+        }
+        match status {
+            FileStatus::Tracked => return Ok(true),
+            FileStatus::Untracked => self.untracked_paths += 1,
+            FileStatus::Ignored => self.ignored_paths += 1,
+        }
+        debug!("{} is not tracked by git, skipping it", relative_path);
+        Ok(false)
+    }
+
+    /// run `read` against the full path of a workspace-relative path,
+    /// returning None if the path is missing or was skipped.
+    pub fn read_with<T>(
+        &mut self,
+        relative_path: &str,
+        read: impl FnOnce(&Path) -> std::io::Result<T>,
+    ) -> Result<Option<T>> {
+        match read(&self.root.join(relative_path)) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                debug!("{} not found in the workspace", relative_path);
+                self.missing_paths += 1;
+                Ok(None)
+            }
+            Err(e) if self.strict_io => Err(DepsawError::IoError(std::io::Error::new(
+                e.kind(),
+                format!("unable to read {}: {}", relative_path, e),
+            ))),
+            Err(e) => {
+                warn!("skipping {}: {}", relative_path, e);
+                self.skipped_paths.push(SkippedPath {
+                    path: relative_path.to_string(),
+                    error_kind: e.kind().to_string(),
+                });
+                Ok(None)
+            }
+        }
+    }
+
+    /// number of paths that were not found.
+    pub fn missing_paths(&self) -> usize {
+        self.missing_paths
+    }
+
+    /// number of paths that were untracked, and not ignored.
+    pub fn untracked_paths(&self) -> usize {
+        self.untracked_paths
+    }
+
+    /// number of paths that were ignored.
+    pub fn ignored_paths(&self) -> usize {
+        self.ignored_paths
+    }
+
+    pub fn skipped_paths(&self) -> &[SkippedPath] {
+        &self.skipped_paths
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn read(workspace: &mut Workspace, path: &str) -> Result<Option<String>> {
+        workspace.read_with(path, |path| std::fs::read_to_string(path))
+    }
+
+    /// a workspace with a readable file, and a directory where a file is
+    /// expected, which fails to read even as root.
+    fn workspace_dir() -> TempDir {
+        let dir = TempDir::new("workspace");
+        dir.write("a/A.java", "a");
+        dir.write("a/Dir.java/inner", "");
+        dir
+    }
+
+    #[test]
+    fn unreadable_paths_are_skipped() {
+        let dir = workspace_dir();
+        let mut workspace = Workspace::new(dir.path_str(), false);
+        assert_eq!(
+            read(&mut workspace, "a/A.java").unwrap(),
+            Some("a".to_string())
+        );
+        assert_eq!(read(&mut workspace, "a/Dir.java").unwrap(), None);
+        assert_eq!(read(&mut workspace, "a/Missing.java").unwrap(), None);
+        let skipped: Vec<_> = workspace
+            .skipped_paths()
+            .iter()
+            .map(|p| p.path.as_str())
+            .collect();
+        assert_eq!(skipped, vec!["a/Dir.java"]);
+        assert_eq!(workspace.missing_paths(), 1);
+    }
+
+    #[test]
+    fn unreadable_paths_are_fatal_with_strict_io() {
+        let dir = workspace_dir();
+        let mut workspace = Workspace::new(dir.path_str(), true);
+        let err = read(&mut workspace, "a/Dir.java").unwrap_err();
+        assert!(err.to_string().contains("a/Dir.java"));
+        // missing files are never fatal.
+        assert_eq!(read(&mut workspace, "a/Missing.java").unwrap(), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn permission_denied_paths_are_skipped() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = workspace_dir();
+        let path = dir.path().join("a/A.java");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o000)).unwrap();
+        if std::fs::read(&path).is_ok() {
+            // running as root, which can read anything.
+            return;
+        }
+        let mut workspace = Workspace::new(dir.path_str(), false);
+        assert_eq!(read(&mut workspace, "a/A.java").unwrap(), None);
+        assert_eq!(
+            workspace.skipped_paths(),
+            &[SkippedPath {
+                path: "a/A.java".to_string(),
+                error_kind: std::io::ErrorKind::PermissionDenied.to_string(),
+            }]
+        );
+        let mut strict = Workspace::new(dir.path_str(), true);
+        assert!(matches!(
+            read(&mut strict, "a/A.java"),
+            Err(DepsawError::IoError(e)) if e.kind() == std::io::ErrorKind::PermissionDenied
+        ));
+    }
+}
@@ -0,0 +1,103 @@
+//! Runs the depsaw binary with saved bazel query output piped to its stdin.
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+const EXAMPLE: &str = include_str!("../examples/dependencies.ndjson");
+
+/// a directory in the temporary directory, removed when dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("depsaw-it-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    fn join(&self, name: &str) -> String {
+        self.0.join(name).to_str().unwrap().to_string()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn gzip(content: &[u8]) -> Vec<u8> {
+    let mut child = Command::new("gzip")
+        .arg("-c")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(content).unwrap();
+    child.wait_with_output().unwrap().stdout
+}
+
+/// run depsaw with the input on its stdin.
+fn depsaw(args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_depsaw"))
+        .args(args)
+        .env("DEPSAW_NO_UPDATE_CHECK", "1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "depsaw {} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+/// precalculate a dependency graph from the piped query output, returning
+/// the deps of `//:runner` in it.
+fn runner_deps(dir: &TempDir, name: &str, input: &[u8]) -> String {
+    let graph = dir.join(name);
+    depsaw(
+        &[
+            "precalculate",
+            "--output",
+            &graph,
+            "bazel-deps",
+            "--bazel-query-output",
+            "-",
+        ],
+        input,
+    );
+    let output = depsaw(
+        &[
+            "query",
+            "--bazel-analysis-file",
+            &graph,
+            "--format",
+            "json",
+            "deps",
+            "//:runner",
+            "--transitive",
+        ],
+        &[],
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn query_output_is_read_from_stdin() {
+    let dir = TempDir::new("stdin");
+    let plain = runner_deps(&dir, "plain.rkyv", EXAMPLE.as_bytes());
+    assert!(
+        plain.contains("//src/main/java/com/example/dishes:pizza"),
+        "{}",
+        plain
+    );
+    let gzipped = runner_deps(&dir, "gzipped.rkyv", &gzip(EXAMPLE.as_bytes()));
+    assert_eq!(gzipped, plain);
+}